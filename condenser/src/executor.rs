@@ -0,0 +1,504 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use log::{debug, error, info, warn};
+
+use crate::output_files::{clean_temp_files, temp_dir_path};
+use crate::plan::{Claim, Plan};
+use crate::state::StateDatabase;
+use crate::transformer::TransformerInstance;
+
+/// The shared state of the worker pool, guarded by a mutex.
+struct Queue<'a> {
+    /// The claims yet to be processed, for each transformer in the plan.
+    pending: Vec<VecDeque<&'a Claim>>,
+
+    /// The number of claims currently being processed, for each transformer in the plan.
+    active: Vec<usize>,
+
+    /// The maximum number of claims that may be processed concurrently, for each
+    /// transformer in the plan.
+    limits: Vec<usize>,
+
+    /// The failed inputs and their errors, for each transformer in the plan.
+    errors: Vec<Vec<(PathBuf, Box<dyn std::error::Error + Send + Sync>)>>,
+
+    /// The state database, updated as outputs are produced.
+    state: StateDatabase,
+
+    /// The number of records updated since the state database was last saved.
+    unsaved: usize,
+}
+
+impl<'a> Queue<'a> {
+    /// Takes the next claim from the highest priority transformer that has work
+    /// remaining and hasn't reached its concurrency limit.
+    fn take(&mut self) -> Option<(usize, &'a Claim)> {
+        for index in 0..self.pending.len() {
+            if self.active[index] < self.limits[index] {
+                if let Some(claim) = self.pending[index].pop_front() {
+                    self.active[index] += 1;
+                    return Some((index, claim));
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns true when there are no claims left to hand out.
+    fn is_drained(&self) -> bool {
+        self.pending.iter().all(|claims| claims.is_empty())
+    }
+}
+
+/// The number of record updates after which the state database is saved, so that
+/// little progress is lost if a run is interrupted.
+const STATE_SAVE_INTERVAL: usize = 100;
+
+/// Hands out unique paths within the temporary directory.
+struct TempFiles {
+    /// The temporary directory.
+    dir: PathBuf,
+
+    /// The number of paths handed out so far.
+    count: AtomicUsize,
+}
+
+impl TempFiles {
+    /// Returns a new temporary path, keeping the file name of output_path so that
+    /// transformers can still rely on its extension.
+    fn next_path(&self, output_path: &Path) -> PathBuf {
+        let id = self.count.fetch_add(1, Ordering::Relaxed);
+        let mut file_name = std::ffi::OsString::from(format!("{}-", id));
+        file_name.push(output_path.file_name().unwrap_or_default());
+        self.dir.join(file_name)
+    }
+}
+
+/// Runs each transformer over its claims in the plan, using up to jobs worker threads.
+/// transformers must be the same (sorted) slice the plan was created from. Outputs are
+/// written to a temporary file and only moved into place once the transformation
+/// succeeds. The state database is updated with every output produced, and records
+/// of outputs no longer in the plan are dropped. Returns the number of files that
+/// failed to transform.
+pub fn execute_plan(
+    plan: &Plan,
+    transformers: &[TransformerInstance],
+    jobs: usize,
+) -> Result<usize, std::io::Error> {
+    let jobs = jobs.max(1);
+    clean_temp_files(&plan.output_dir)?;
+    let state = StateDatabase::load(&plan.output_dir).unwrap_or_else(|err| {
+        warn!("Failed to load the state database, all outputs will be considered unknown - {}", err);
+        StateDatabase::empty(&plan.output_dir)
+    });
+    let temp_files = TempFiles {
+        dir: temp_dir_path(&plan.output_dir),
+        count: AtomicUsize::new(0),
+    };
+
+    let mut queue = Queue {
+        pending: Vec::new(),
+        active: vec![0; plan.transformers.len()],
+        limits: Vec::new(),
+        errors: Vec::new(),
+        state,
+        unsaved: 0,
+    };
+    let mut instances = Vec::new();
+    for transformer_plan in &plan.transformers {
+        match transformers.get(transformer_plan.transformer) {
+            Some(transformer) if transformer.name == transformer_plan.name => {
+                queue.pending.push(transformer_plan.claims.iter().collect());
+                queue.limits.push(transformer.max_jobs.unwrap_or(jobs).max(1));
+                instances.push(Some(transformer));
+            }
+            _ => {
+                error!("Transformer '{}' is not in the transformer list", &transformer_plan.name);
+                queue.pending.push(VecDeque::new());
+                queue.limits.push(0);
+                instances.push(None);
+            }
+        }
+        queue.errors.push(Vec::new());
+    }
+
+    info!(
+        "Running {} transformer(s) with {} job(s)...",
+        plan.transformers.len(),
+        jobs
+    );
+    let queue = Mutex::new(queue);
+    let available = Condvar::new();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| run_worker(plan, &instances, &queue, &available, &temp_files));
+        }
+    });
+
+    let mut queue = queue.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let output_paths = plan.output_paths();
+    queue.state.retain(|path, _| output_paths.contains(path));
+    queue.state.save()?;
+
+    let mut error_count = 0;
+    for ((transformer_plan, instance), errors) in plan.transformers.iter().zip(&instances).zip(queue.errors) {
+        if instance.is_none() {
+            error_count += transformer_plan.claims.len();
+            continue;
+        }
+
+        info!(
+            "Transformer '{}' processing completed - {} error(s)",
+            &transformer_plan.name,
+            errors.len()
+        );
+        error_count += errors.len();
+        for error in errors {
+            warn!(
+                "Transformer {} encountered an error transforming '{}' - {}",
+                &transformer_plan.name,
+                error.0.to_string_lossy(),
+                error.1
+            )
+        }
+    }
+    Ok(error_count)
+}
+
+/// Repeatedly takes a claim from the queue and processes it, until the queue is drained.
+fn run_worker(
+    plan: &Plan,
+    instances: &[Option<&TransformerInstance>],
+    queue: &Mutex<Queue>,
+    available: &Condvar,
+    temp_files: &TempFiles,
+) {
+    loop {
+        let (index, claim) = {
+            let mut queue = queue.lock().unwrap();
+            loop {
+                if let Some(next) = queue.take() {
+                    break next;
+                }
+                if queue.is_drained() {
+                    return;
+                }
+                // Work remains, but every transformer with work is at its limit.
+                queue = available.wait(queue).unwrap();
+            }
+        };
+
+        // Claims are only queued for transformers that were found.
+        let transformer = instances[index].expect("Expected claim to have a transformer");
+        debug!(
+            "Transformer '{}' processing '{}'",
+            &transformer.name,
+            claim.input_path().to_string_lossy()
+        );
+        let temp_path = temp_files.next_path(&claim.output_path);
+        let record = queue.lock().unwrap().state.get(&claim.output_path).cloned();
+        // A panic is reported as an error, so that the claim's slot is still released
+        // and workers waiting on it are woken.
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            transformer.process_claim(claim, &plan.output_dir, &temp_path, record.as_ref())
+        }))
+        .unwrap_or_else(|payload| Err(panic_error(payload)));
+
+        let mut queue = queue.lock().unwrap();
+        queue.active[index] -= 1;
+        match result {
+            Ok(Some(record)) => {
+                queue.state.insert(claim.output_path.clone(), record);
+                queue.unsaved += 1;
+                if queue.unsaved >= STATE_SAVE_INTERVAL {
+                    queue.unsaved = 0;
+                    if let Err(err) = queue.state.save() {
+                        warn!("Failed to save the state database - {}", err);
+                    }
+                }
+            }
+            Ok(None) => (),
+            Err(err) => queue.errors[index].push((claim.input_path(), err)),
+        }
+        available.notify_all();
+    }
+}
+
+/// Converts the payload of a panic into an error describing it.
+fn panic_error(payload: Box<dyn Any + Send>) -> Box<dyn std::error::Error + Send + Sync> {
+    let message = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().unwrap_or(&"unknown cause").to_string(),
+    };
+    format!("Transformer panicked - {}", message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::plan::TransformerPlan;
+    use crate::transformer::{InputId, OutputId, OverwriteBehavior, Transformer};
+
+    /// What the stub transformer does once it has been counted.
+    #[derive(Debug, Default)]
+    enum Outcome {
+        /// Writes an empty output.
+        #[default]
+        Succeed,
+        /// Writes a partial output, then fails.
+        Fail,
+        /// Panics instead of transforming.
+        Panic,
+    }
+
+    /// Handles every input, outputting to the same relative path, and records the total
+    /// and peak number of concurrent transformations.
+    #[derive(Debug, Default)]
+    struct StubTransformer {
+        outcome: Outcome,
+        current: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+        total: Arc<AtomicUsize>,
+        settings: String,
+    }
+
+    impl Transformer for StubTransformer {
+        fn can_handle(&self, _input: &InputId) -> bool {
+            true
+        }
+
+        fn determine_output_id(&self, input: &InputId) -> OutputId {
+            OutputId(input.file_path().as_os_str().to_owned())
+        }
+
+        fn determine_output_path(&self, input: &InputId) -> PathBuf {
+            input.file_path().to_path_buf()
+        }
+
+        fn transform(&self, input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(current, Ordering::SeqCst);
+            self.total.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(5));
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            match self.outcome {
+                Outcome::Succeed => std::fs::write(output, [])?,
+                Outcome::Fail => {
+                    std::fs::write(output, "partial")?;
+                    return Err("Transformation failed".into());
+                }
+                Outcome::Panic => panic!("Unexpected input {}", input.to_string_lossy()),
+            }
+            Ok(())
+        }
+
+        fn fingerprint(&self) -> String {
+            self.settings.clone()
+        }
+    }
+
+    /// Creates count input files within test_dir, and a plan claiming all of them.
+    fn plan_with_claims(test_dir: &Path, count: usize) -> Plan {
+        let input_dir = test_dir.join("input");
+        std::fs::create_dir_all(&input_dir).unwrap();
+        let mut plan = Plan::new(test_dir.join("output"));
+        plan.transformers.push(TransformerPlan {
+            transformer: 0,
+            name: "Counting".to_string(),
+            claims: (0..count)
+                .map(|i| {
+                    std::fs::write(input_dir.join(i.to_string()), i.to_string()).unwrap();
+                    Claim {
+                        input_dir: input_dir.clone(),
+                        file_path: PathBuf::from(i.to_string()),
+                        output_id: OutputId(i.to_string().into()),
+                        output_path: PathBuf::from(i.to_string()),
+                    }
+                })
+                .collect(),
+        });
+        plan
+    }
+
+    #[test]
+    fn max_jobs_limits_concurrency() {
+        let peak = Arc::new(AtomicUsize::new(0));
+        let transformer = StubTransformer {
+            peak: peak.clone(),
+            ..Default::default()
+        };
+        let mut instance =
+            TransformerInstance::new(0, OverwriteBehavior::Always, "Counting".to_string(), Box::new(transformer));
+        instance.max_jobs = Some(2);
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let errors = execute_plan(&plan_with_claims(test_dir.path(), 20), &[instance], 8).unwrap();
+
+        assert_eq!(errors, 0);
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn failed_transformations_leave_outputs_untouched() {
+        let transformer = Box::new(StubTransformer {
+            outcome: Outcome::Fail,
+            ..Default::default()
+        });
+        let instance = TransformerInstance::new(0, OverwriteBehavior::Always, "Failing".to_string(), transformer);
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let mut plan = plan_with_claims(test_dir.path(), 2);
+        plan.transformers[0].name = "Failing".to_string();
+        std::fs::create_dir_all(&plan.output_dir).unwrap();
+        std::fs::write(plan.output_dir.join("0"), "existing").unwrap();
+        let errors = execute_plan(&plan, &[instance], 2).unwrap();
+
+        let existing = std::fs::read_to_string(plan.output_dir.join("0")).unwrap();
+        let missing = plan.output_dir.join("1").exists();
+        let temp_files = std::fs::read_dir(temp_dir_path(&plan.output_dir)).unwrap().count();
+        assert_eq!(errors, 2);
+        assert_eq!(existing, "existing");
+        assert!(!missing);
+        assert_eq!(temp_files, 0);
+    }
+
+    #[test]
+    fn panics_count_as_errors() {
+        let transformer = Box::new(StubTransformer {
+            outcome: Outcome::Panic,
+            ..Default::default()
+        });
+        let mut instance = TransformerInstance::new(0, OverwriteBehavior::Always, "Panicking".to_string(), transformer);
+        instance.max_jobs = Some(1);
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let mut plan = plan_with_claims(test_dir.path(), 3);
+        plan.transformers[0].name = "Panicking".to_string();
+        let errors = execute_plan(&plan, &[instance], 2).unwrap();
+
+        assert_eq!(errors, 3);
+    }
+
+    #[test]
+    fn mismatched_transformer_counts_as_errors() {
+        let instance = TransformerInstance::new(
+            0,
+            OverwriteBehavior::Always,
+            "Something else".to_string(),
+            Box::new(StubTransformer::default()),
+        );
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let errors = execute_plan(&plan_with_claims(test_dir.path(), 3), &[instance], 2).unwrap();
+
+        assert_eq!(errors, 3);
+    }
+
+    #[test]
+    fn if_changed_skips_unchanged_inputs() {
+        let total = Arc::new(AtomicUsize::new(0));
+        let transformer = StubTransformer {
+            total: total.clone(),
+            ..Default::default()
+        };
+        let instance =
+            TransformerInstance::new(0, OverwriteBehavior::IfChanged, "Counting".to_string(), Box::new(transformer));
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let plan = plan_with_claims(test_dir.path(), 3);
+        execute_plan(&plan, std::slice::from_ref(&instance), 2).unwrap();
+        let first_run = total.load(Ordering::SeqCst);
+
+        execute_plan(&plan, std::slice::from_ref(&instance), 2).unwrap();
+        let second_run = total.load(Ordering::SeqCst) - first_run;
+
+        std::fs::write(plan.transformers[0].claims[0].input_path(), "changed").unwrap();
+        execute_plan(&plan, std::slice::from_ref(&instance), 2).unwrap();
+        let third_run = total.load(Ordering::SeqCst) - first_run - second_run;
+
+        assert_eq!(first_run, 3);
+        assert_eq!(second_run, 0);
+        assert_eq!(third_run, 1);
+    }
+
+    #[test]
+    fn retained_claims_keep_their_records() {
+        let total = Arc::new(AtomicUsize::new(0));
+        let transformer = StubTransformer {
+            total: total.clone(),
+            ..Default::default()
+        };
+        let instance =
+            TransformerInstance::new(0, OverwriteBehavior::IfChanged, "Counting".to_string(), Box::new(transformer));
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let plan = plan_with_claims(test_dir.path(), 3);
+        execute_plan(&plan, std::slice::from_ref(&instance), 2).unwrap();
+        let mut partial_plan = plan.clone();
+        partial_plan.retain_claims(|_, claim| claim.file_path != Path::new("0"));
+        execute_plan(&partial_plan, std::slice::from_ref(&instance), 2).unwrap();
+        let first_runs = total.load(Ordering::SeqCst);
+
+        execute_plan(&plan, std::slice::from_ref(&instance), 2).unwrap();
+        let full_run = total.load(Ordering::SeqCst) - first_runs;
+
+        assert_eq!(first_runs, 3);
+        assert_eq!(full_run, 0);
+    }
+
+    #[test]
+    fn if_newer_reruns_when_settings_change() {
+        let total = Arc::new(AtomicUsize::new(0));
+        let instance_with = |settings: &str| {
+            let transformer = StubTransformer {
+                total: total.clone(),
+                settings: settings.to_string(),
+                ..Default::default()
+            };
+            TransformerInstance::new(0, OverwriteBehavior::IfNewer, "Counting".to_string(), Box::new(transformer))
+        };
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let plan = plan_with_claims(test_dir.path(), 2);
+        execute_plan(&plan, &[instance_with("96K")], 2).unwrap();
+        execute_plan(&plan, &[instance_with("96K")], 2).unwrap();
+        let unchanged_runs = total.load(Ordering::SeqCst);
+
+        execute_plan(&plan, &[instance_with("128K")], 2).unwrap();
+        let changed_run = total.load(Ordering::SeqCst) - unchanged_runs;
+
+        assert_eq!(unchanged_runs, 2);
+        assert_eq!(changed_run, 2);
+    }
+
+    #[test]
+    fn if_newer_keeps_unrecorded_outputs() {
+        let total = Arc::new(AtomicUsize::new(0));
+        let transformer = StubTransformer {
+            total: total.clone(),
+            ..Default::default()
+        };
+        let instance =
+            TransformerInstance::new(0, OverwriteBehavior::IfNewer, "Counting".to_string(), Box::new(transformer));
+
+        let test_dir = tempfile::tempdir().unwrap();
+        let plan = plan_with_claims(test_dir.path(), 2);
+        execute_plan(&plan, std::slice::from_ref(&instance), 2).unwrap();
+        let recorded_runs = total.load(Ordering::SeqCst);
+
+        StateDatabase::empty(&plan.output_dir).save().unwrap();
+        execute_plan(&plan, std::slice::from_ref(&instance), 2).unwrap();
+        let unrecorded_runs = total.load(Ordering::SeqCst) - recorded_runs;
+
+        assert_eq!(recorded_runs, 2);
+        assert_eq!(unrecorded_runs, 0);
+    }
+}
@@ -0,0 +1,225 @@
+use std::path::{Path, PathBuf};
+
+use crate::filters::{Candidate, FilterExplanation};
+use crate::input_files::InputDirectory;
+use crate::plan::{ClaimSource, Plan};
+use crate::plan_transformations;
+use crate::transformer::{InputId, OutputId, TransformerInstance};
+
+/// Explains how a single input file was handled by an input directory, and by each transformer.
+#[derive(Clone, Debug)]
+pub struct InputExplanation {
+    /// The absolute path to the input directory containing the file.
+    pub input_dir: PathBuf,
+
+    /// The priority of the input directory.
+    pub priority: u32,
+
+    /// The path to the input file, relative to input_dir.
+    pub file_path: PathBuf,
+
+    /// Why the input directory's filters accepted or rejected the file.
+    pub filter: FilterExplanation,
+
+    /// How each transformer treated the file, highest priority first.
+    pub transformers: Vec<TransformerExplanation>,
+
+    /// What ultimately happened to the file.
+    pub outcome: InputOutcome,
+}
+
+/// Explains how a transformer treated an input file.
+#[derive(Clone, Debug)]
+pub struct TransformerExplanation {
+    /// The display name of the transformer.
+    pub name: String,
+
+    /// The priority of the transformer.
+    pub priority: u32,
+
+    /// Why the transformer's filters accepted or rejected the file.
+    pub filter: FilterExplanation,
+
+    /// Whether or not the transformer can handle the file. None if its filters rejected
+    /// the file, in which case it isn't asked.
+    pub can_handle: Option<bool>,
+
+    /// The ID of the output the transformer would produce. None unless it can handle the file.
+    pub output_id: Option<OutputId>,
+}
+
+/// What ultimately happened to an input file.
+#[derive(Clone, Debug)]
+pub enum InputOutcome {
+    /// The file was claimed by the transformer, and will be output to output_path.
+    Claimed {
+        /// The display name of the transformer.
+        transformer: String,
+        /// The path to the output file, relative to the output directory.
+        output_path: PathBuf,
+    },
+    /// The file's output had already been claimed by another input.
+    Blocked {
+        /// The ID of the contested output.
+        output_id: OutputId,
+        /// The input that claimed the output.
+        winner: ClaimSource,
+    },
+    /// The file was enumerated, but no transformer claimed it.
+    Unclaimed,
+    /// The file wasn't enumerated, because it was rejected by the input directory's
+    /// filters or isn't a file.
+    NotEnumerated,
+}
+
+/// Explains how the file at path is handled, for every input directory containing it. The
+/// full plan is created to find out which claim won, so transformers are sorted by priority
+/// as in plan_transformations. The path must be absolute or relative to the working directory.
+pub fn explain_input<'a, DirIter, P>(
+    transformers: &mut [TransformerInstance],
+    input_dirs: DirIter,
+    output_dir_path: P,
+    path: &Path,
+) -> Result<Vec<InputExplanation>, std::io::Error>
+where
+    DirIter: IntoIterator<Item = &'a InputDirectory>,
+    P: AsRef<Path>,
+{
+    let input_dirs: Vec<&InputDirectory> = input_dirs.into_iter().collect();
+    let plan = plan_transformations(transformers, input_dirs.iter().copied(), output_dir_path)?;
+    let path = resolve(path)?;
+
+    let mut explanations = Vec::new();
+    for input_dir in input_dirs {
+        let file_path = match path.strip_prefix(resolve(&input_dir.path)?) {
+            Ok(file_path) => file_path,
+            Err(_) => continue,
+        };
+        let candidate = Candidate::in_dir(&input_dir.path, file_path);
+        let input_id = InputId {
+            dir_path: &input_dir.path,
+            file_path,
+        };
+        let transformers = transformers
+            .iter()
+            .map(|transformer| {
+                let filter = transformer.filter.explain(&candidate);
+                let can_handle = filter.accepted.then(|| transformer.transformer.can_handle(&input_id));
+                TransformerExplanation {
+                    name: transformer.name.clone(),
+                    priority: transformer.priority,
+                    filter,
+                    can_handle,
+                    output_id: can_handle
+                        .unwrap_or(false)
+                        .then(|| transformer.transformer.determine_output_id(&input_id)),
+                }
+            })
+            .collect();
+
+        explanations.push(InputExplanation {
+            input_dir: input_dir.path.clone(),
+            priority: input_dir.priority,
+            file_path: file_path.to_path_buf(),
+            filter: input_dir.filters.explain(&candidate),
+            transformers,
+            outcome: find_outcome(&plan, &input_dir.path, file_path),
+        });
+    }
+    Ok(explanations)
+}
+
+/// Finds what the plan does with the file at file_path within input_dir.
+fn find_outcome(plan: &Plan, input_dir: &Path, file_path: &Path) -> InputOutcome {
+    let is_file = |dir: &Path, path: &Path| dir == input_dir && path == file_path;
+    if let Some((transformer, claim)) = plan.claims().find(|(_, claim)| is_file(&claim.input_dir, &claim.file_path)) {
+        return InputOutcome::Claimed {
+            transformer: transformer.name.clone(),
+            output_path: claim.output_path.clone(),
+        };
+    }
+    if let Some(conflict) = plan.conflicts.iter().find(|conflict| is_file(&conflict.loser.input_dir, &conflict.loser.file_path)) {
+        return InputOutcome::Blocked {
+            output_id: conflict.output_id.clone(),
+            winner: conflict.winner.clone(),
+        };
+    }
+    if plan.unclaimed.iter().any(|unclaimed| is_file(&unclaimed.input_dir, &unclaimed.file_path)) {
+        return InputOutcome::Unclaimed;
+    }
+    InputOutcome::NotEnumerated
+}
+
+/// Resolves path into an absolute path with symbolic links resolved in its parent
+/// directory, if it exists. The file name is kept as is, so that a symbolic link to a
+/// file is explained as the link within its input directory, rather than its target.
+fn resolve(path: &Path) -> Result<PathBuf, std::io::Error> {
+    let path = std::path::absolute(path)?;
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => Ok(resolve_dir(parent)?.join(file_name)),
+        _ => resolve_dir(&path),
+    }
+}
+
+/// Resolves the path to a directory into an absolute path with symbolic links resolved,
+/// if it exists.
+fn resolve_dir(path: &Path) -> Result<PathBuf, std::io::Error> {
+    match std::fs::canonicalize(path) {
+        Ok(path) => Ok(path),
+        Err(_) => std::path::absolute(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::FilterAction;
+    use crate::test_fixtures::{copy, input_dir};
+
+    #[test]
+    fn explains_claimed_rejected_blocked_and_linked_files() {
+        let test_dir = std::env::temp_dir().join("condenser-explain-test");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        for dir in ["high", "low", "elsewhere"] {
+            std::fs::create_dir_all(test_dir.join(dir)).unwrap();
+        }
+        for file in ["high/a.flac", "high/b.txt", "low/a.flac", "elsewhere/target.flac"] {
+            std::fs::write(test_dir.join(file), "").unwrap();
+        }
+        std::os::unix::fs::symlink(test_dir.join("elsewhere/target.flac"), test_dir.join("high/link.flac")).unwrap();
+
+        let mut transformers = [copy("Audio", 50, &["*.flac"])];
+        let mut high = input_dir(test_dir.join("high"), 100);
+        high.filters.append_glob(glob::Pattern::new("*.txt").unwrap(), FilterAction::Reject);
+        let input_dirs = [high, input_dir(test_dir.join("low"), 50)];
+        let mut explain = |path: &str| {
+            explain_input(&mut transformers, &input_dirs, test_dir.join("out"), &test_dir.join(path)).unwrap()
+        };
+
+        let accepted = explain("high/a.flac");
+        let rejected = explain("high/b.txt");
+        let blocked = explain("low/a.flac");
+        let linked = explain("high/link.flac");
+        let outside = explain("elsewhere/target.flac");
+        std::fs::remove_dir_all(&test_dir).unwrap();
+
+        assert_eq!(accepted.len(), 1);
+        assert!(accepted[0].filter.accepted);
+        assert!(matches!(&accepted[0].outcome, InputOutcome::Claimed { transformer, output_path }
+            if transformer == "Audio" && output_path == Path::new("a.flac")));
+
+        assert_eq!(rejected.len(), 1);
+        assert!(!rejected[0].filter.accepted);
+        assert!(matches!(rejected[0].outcome, InputOutcome::NotEnumerated));
+
+        assert_eq!(blocked.len(), 1);
+        assert_eq!(blocked[0].input_dir, test_dir.join("low"));
+        assert!(matches!(&blocked[0].outcome, InputOutcome::Blocked { winner, .. }
+            if winner.input_dir == test_dir.join("high")));
+
+        assert_eq!(linked.len(), 1);
+        assert_eq!(linked[0].file_path, Path::new("link.flac"));
+        assert!(matches!(linked[0].outcome, InputOutcome::Claimed { .. }));
+        assert!(outside.is_empty());
+    }
+}
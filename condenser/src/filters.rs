@@ -1,36 +1,244 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
 use std::convert::AsRef;
 use std::fmt::Display;
-use std::path::Path;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use crate::sniff::{sniff_file, FileType};
+
+/// A file being tested against filters. Patterns match against its relative path,
+/// and its metadata is only read if a pattern needs it. Its metadata and type are
+/// cached, so a file tested against several filter sets should use one candidate.
+#[derive(Debug)]
+pub struct Candidate<'a> {
+    /// The path that path patterns are matched against.
+    path: Cow<'a, Path>,
+
+    /// The directory the path is relative to, if the file can be read.
+    root: Option<&'a Path>,
+
+    /// The file's metadata, once it has been read. None if it couldn't be read.
+    metadata: OnceCell<Option<Metadata>>,
+
+    /// The file's detected type, once it has been sniffed. None if it couldn't be detected.
+    file_type: OnceCell<Option<FileType>>,
+}
+
+impl<'a> Candidate<'a> {
+    /// Creates a candidate that can only be matched by its path. Metadata and content
+    /// patterns never match it.
+    pub fn new(path: impl Into<Cow<'a, Path>>) -> Candidate<'a> {
+        Candidate {
+            path: path.into(),
+            root: None,
+            metadata: OnceCell::new(),
+            file_type: OnceCell::new(),
+        }
+    }
+
+    /// Creates a candidate for the file at path, relative to root.
+    pub fn in_dir(root: &'a Path, path: impl Into<Cow<'a, Path>>) -> Candidate<'a> {
+        Candidate {
+            path: path.into(),
+            root: Some(root),
+            metadata: OnceCell::new(),
+            file_type: OnceCell::new(),
+        }
+    }
+
+    /// Returns the path that patterns are matched against.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the path that patterns are matched against, consuming the candidate.
+    pub fn into_path(self) -> PathBuf {
+        self.path.into_owned()
+    }
+
+    /// Returns the file's metadata, reading it the first time it's needed. Returns None
+    /// if the candidate has no file, or its metadata couldn't be read.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        self.metadata
+            .get_or_init(|| self.root.and_then(|root| std::fs::metadata(root.join(&self.path)).ok()))
+            .as_ref()
+    }
+
+    /// Returns the file's type, detected from its contents the first time it's needed.
+    /// Returns None if the candidate has no file, or its type couldn't be detected.
+    pub fn file_type(&self) -> Option<FileType> {
+        *self.file_type.get_or_init(|| {
+            self.root
+                .and_then(|root| sniff_file(&root.join(&self.path)).ok())
+                .flatten()
+        })
+    }
+}
 
 /// Defines a pattern that file paths are matched against.
 #[derive(Clone, Debug)]
 pub enum FilterPattern {
     /// A regex filter. Automatically rejects paths that aren't valid UTF-8.
     Regex(regex::Regex),
-    /// A glob filter.
-    Glob(glob::Pattern),
+    /// A glob filter, matched with the given options.
+    Glob(glob::Pattern, GlobOptions),
+    /// Matches if every pattern matches. Matches if there are no patterns.
+    All(Vec<FilterPattern>),
+    /// Matches if any pattern matches. Doesn't match if there are no patterns.
+    Any(Vec<FilterPattern>),
+    /// Matches if the pattern doesn't match.
+    Not(Box<FilterPattern>),
+    /// Matches if the filter set accepts the path.
+    Set(FilterSet),
+    /// Matches files with a size in bytes within the inclusive range.
+    Size {
+        min: Option<u64>,
+        max: Option<u64>,
+    },
+    /// Matches files last modified within the range of times, both exclusive.
+    Modified {
+        after: Option<SystemTime>,
+        before: Option<SystemTime>,
+    },
+    /// Matches files whose time since last modification is within the inclusive range.
+    Age {
+        min: Option<Duration>,
+        max: Option<Duration>,
+    },
+    /// Matches files whose contents are detected to be of the file type, regardless
+    /// of their extension.
+    Content(FileType),
 }
 
 impl FilterPattern {
     // Tests whether or not the pattern matches the given path.
     pub fn is_match<P: AsRef<Path>>(&self, path: P) -> bool {
-        let path = path.as_ref();
+        self.matches(&Candidate::new(path.as_ref()))
+    }
+
+    /// Tests whether or not the pattern matches the candidate. Metadata patterns
+    /// don't match if the candidate's metadata can't be read.
+    pub fn matches(&self, candidate: &Candidate) -> bool {
+        let path = candidate.path();
         match self {
             FilterPattern::Regex(regex) => path.to_str().map_or(false, |str| regex.is_match(str)),
-            FilterPattern::Glob(glob) => glob.matches_path(path),
+            FilterPattern::Glob(glob, options) => glob.matches_path_with(path, options.into()),
+            FilterPattern::All(patterns) => patterns.iter().all(|pattern| pattern.matches(candidate)),
+            FilterPattern::Any(patterns) => patterns.iter().any(|pattern| pattern.matches(candidate)),
+            FilterPattern::Not(pattern) => !pattern.matches(candidate),
+            FilterPattern::Set(set) => set.accepts(candidate),
+            FilterPattern::Size { min, max } => candidate.metadata().map_or(false, |metadata| {
+                in_range(&metadata.len(), min.as_ref(), max.as_ref())
+            }),
+            FilterPattern::Modified { after, before } => modified(candidate).map_or(false, |modified| {
+                after.map_or(true, |after| modified > after) && before.map_or(true, |before| modified < before)
+            }),
+            FilterPattern::Age { min, max } => modified(candidate).map_or(false, |modified| {
+                let age = SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO);
+                in_range(&age, min.as_ref(), max.as_ref())
+            }),
+            FilterPattern::Content(file_type) => candidate.file_type() == Some(*file_type),
         }
     }
 }
 
+/// Returns the time the candidate was last modified, if it can be read.
+fn modified(candidate: &Candidate) -> Option<SystemTime> {
+    candidate.metadata().and_then(|metadata| metadata.modified().ok())
+}
+
+/// Returns whether or not value is within the inclusive range, with missing bounds
+/// being unbounded.
+fn in_range<T: PartialOrd>(value: &T, min: Option<&T>, max: Option<&T>) -> bool {
+    min.map_or(true, |min| value >= min) && max.map_or(true, |max| value <= max)
+}
+
 impl Display for FilterPattern {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             FilterPattern::Regex(regex) => write!(f, "(Regex: {})", regex.as_str()),
-            FilterPattern::Glob(glob) => write!(f, "(Glob: {})", glob.as_str()),
+            FilterPattern::Glob(glob, options) => {
+                write!(f, "(Glob: {}", glob.as_str())?;
+                if !options.case_sensitive {
+                    write!(f, ", case insensitive")?;
+                }
+                if options.literal_separator {
+                    write!(f, ", literal separator")?;
+                }
+                if options.literal_leading_dot {
+                    write!(f, ", literal leading dot")?;
+                }
+                write!(f, ")")
+            }
+            FilterPattern::All(patterns) => write_patterns(f, "All", patterns),
+            FilterPattern::Any(patterns) => write_patterns(f, "Any", patterns),
+            FilterPattern::Not(pattern) => write!(f, "(Not: {})", pattern),
+            FilterPattern::Set(set) => {
+                write!(f, "(Set:")?;
+                for filter in &set.filters {
+                    write!(f, " {}", filter)?;
+                }
+                write!(f, " else {})", if set.accept_unmatched { "Accept" } else { "Reject" })
+            }
+            FilterPattern::Size { min, max } => write!(f, "(Size: {:?} to {:?} bytes)", min, max),
+            FilterPattern::Modified { after, before } => {
+                write!(f, "(Modified: after {:?}, before {:?})", after, before)
+            }
+            FilterPattern::Age { min, max } => write!(f, "(Age: {:?} to {:?})", min, max),
+            FilterPattern::Content(file_type) => write!(f, "(Content: {})", file_type),
+        }
+    }
+}
+
+/// Options controlling how glob patterns are matched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlobOptions {
+    /// Whether or not letters must match case.
+    pub case_sensitive: bool,
+
+    /// Whether or not path separators must be matched by a literal separator, rather
+    /// than by wildcards.
+    pub literal_separator: bool,
+
+    /// Whether or not a leading dot in a file or directory name must be matched by
+    /// a literal dot, rather than by wildcards.
+    pub literal_leading_dot: bool,
+}
+
+impl Default for GlobOptions {
+    /// Returns case sensitive options that allow wildcards to match separators and
+    /// leading dots.
+    fn default() -> Self {
+        GlobOptions {
+            case_sensitive: true,
+            literal_separator: false,
+            literal_leading_dot: false,
         }
     }
 }
 
+impl From<&GlobOptions> for glob::MatchOptions {
+    fn from(options: &GlobOptions) -> Self {
+        glob::MatchOptions {
+            case_sensitive: options.case_sensitive,
+            require_literal_separator: options.literal_separator,
+            require_literal_leading_dot: options.literal_leading_dot,
+        }
+    }
+}
+
+/// Writes a list of patterns, labeled with the name of the combining pattern.
+fn write_patterns(f: &mut std::fmt::Formatter<'_>, name: &str, patterns: &[FilterPattern]) -> std::fmt::Result {
+    write!(f, "({}:", name)?;
+    for pattern in patterns {
+        write!(f, " {}", pattern)?;
+    }
+    write!(f, ")")
+}
+
 /// The action that is performed if a file matches a filter.
 #[derive(Copy, Clone, Debug)]
 pub enum FilterAction {
@@ -57,6 +265,9 @@ pub struct Filter {
     /// The action that is taken if the file path matches this filter.
     /// The file is ignored otherwise.
     action: FilterAction,
+    /// Whether or not the pattern is a regex built from its pattern string alone, so that
+    /// it can be recompiled from the string without losing any flags.
+    exact_regex: bool,
 }
 
 impl Filter {
@@ -65,13 +276,30 @@ impl Filter {
         Filter {
             pattern,
             action,
+            exact_regex: false,
         }
     }
 
+    /// Returns the pattern that file paths are matched against.
+    pub fn pattern(&self) -> &FilterPattern {
+        &self.pattern
+    }
+
+    /// Returns the action that is taken if a file path matches this filter.
+    pub fn action(&self) -> FilterAction {
+        self.action
+    }
+
     /// Tests a file path against this filter, returning the specified
     /// if it maches, or None otherwise.
     pub fn test<P: AsRef<Path>>(&self, path: P) -> Option<FilterAction> {
-        if self.pattern.is_match(path) {
+        self.test_candidate(&Candidate::new(path.as_ref()))
+    }
+
+    /// Tests a candidate against this filter, returning the specified action
+    /// if it matches, or None otherwise.
+    pub fn test_candidate(&self, candidate: &Candidate) -> Option<FilterAction> {
+        if self.pattern.matches(candidate) {
             Some(self.action)
         } else {
             None
@@ -85,7 +313,121 @@ impl Display for Filter {
     }
 }
 
+/// Describes why a filter set accepted or rejected a candidate.
+#[derive(Clone, Debug)]
+pub struct FilterExplanation {
+    /// Whether or not the candidate was accepted.
+    pub accepted: bool,
+
+    /// The index of the first filter that matched the candidate, along with the filter.
+    /// None if no filter matched, and the candidate was accepted or rejected by default.
+    pub filter: Option<(usize, Filter)>,
+}
+
+impl Display for FilterExplanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let result = if self.accepted { "accepted" } else { "rejected" };
+        match &self.filter {
+            Some((index, filter)) => write!(f, "{} by filter #{} {}", result, index + 1, filter),
+            None => write!(f, "{} - no filter matched", result),
+        }
+    }
+}
+
+/// The minimum number of globs and regexes a filter set must contain before they're
+/// compiled into combined matchers. Testing fewer patterns in turn is faster.
+const COMPILE_THRESHOLD: usize = 8;
+
+/// The globs and regexes of a filter set, compiled into combined matchers that test
+/// a path against every pattern at once.
+#[derive(Clone, Debug)]
+struct CompiledFilters {
+    /// The compiled globs.
+    globs: globset::GlobSet,
+    /// The index of the filter each glob in globs came from.
+    glob_filters: Vec<usize>,
+    /// The compiled regexes.
+    regexes: regex::RegexSet,
+    /// The index of the filter each regex in regexes came from.
+    regex_filters: Vec<usize>,
+    /// Whether or not each filter in the set has been compiled. Filters that haven't
+    /// been must still be tested individually.
+    compiled: Vec<bool>,
+}
+
+impl CompiledFilters {
+    /// Compiles every glob and regex in filters that can be compiled without changing
+    /// its meaning. Returns None if there are too few of them to be worth compiling.
+    fn new(filters: &[Filter]) -> Option<CompiledFilters> {
+        let mut globs = globset::GlobSetBuilder::new();
+        let mut glob_filters = Vec::new();
+        let mut regexes = Vec::new();
+        let mut regex_filters = Vec::new();
+        let mut compiled = vec![false; filters.len()];
+        for (index, filter) in filters.iter().enumerate() {
+            match &filter.pattern {
+                FilterPattern::Glob(pattern, options) => {
+                    if let Some(glob) = compile_glob(pattern, options) {
+                        globs.add(glob);
+                        glob_filters.push(index);
+                        compiled[index] = true;
+                    }
+                }
+                // Regexes built elsewhere may have flags that their pattern string doesn't.
+                FilterPattern::Regex(regex) if filter.exact_regex => {
+                    regexes.push(regex.as_str());
+                    regex_filters.push(index);
+                    compiled[index] = true;
+                }
+                _ => (),
+            }
+        }
+        if glob_filters.len() + regex_filters.len() < COMPILE_THRESHOLD {
+            return None;
+        }
+
+        Some(CompiledFilters {
+            globs: globs.build().ok()?,
+            glob_filters,
+            regexes: regex::RegexSet::new(regexes).ok()?,
+            regex_filters,
+            compiled,
+        })
+    }
+
+    /// Returns the index of the first compiled filter that matches the path, if any.
+    fn first_match(&self, path: &Path) -> Option<usize> {
+        // Both glob and regex patterns reject paths that aren't valid UTF-8
+        let path = path.to_str()?;
+        let glob_match = self.globs.matches(path).first().map(|&index| self.glob_filters[index]);
+        let regex_match = self.regexes.matches(path).iter().next().map(|index| self.regex_filters[index]);
+        match (glob_match, regex_match) {
+            (Some(glob), Some(regex)) => Some(glob.min(regex)),
+            (glob, regex) => glob.or(regex),
+        }
+    }
+}
+
+/// Compiles a glob pattern for use in a glob set, or returns None if it can't be compiled
+/// with the same meaning - glob sets treat braces as alternations, handle recursive
+/// wildcards differently, negate character classes with `^` as well as `!`, and can't
+/// require leading dots to be matched literally.
+fn compile_glob(pattern: &glob::Pattern, options: &GlobOptions) -> Option<globset::Glob> {
+    let str = pattern.as_str();
+    if options.literal_leading_dot || str.contains(['{', '}', '[', ']']) || str.contains("**") {
+        return None;
+    }
+    globset::GlobBuilder::new(str)
+        .case_insensitive(!options.case_sensitive)
+        .literal_separator(options.literal_separator)
+        .backslash_escape(false)
+        .build()
+        .ok()
+}
+
 /// A ordered set of filters. A file path is checked against each filter in turn.
+/// Large sets compile their globs and regexes into combined matchers the first time
+/// they're used, while keeping the result of the first matching filter.
 #[derive(Clone, Debug)]
 pub struct FilterSet {
     /// The ordered list of filters to test candidates with.
@@ -93,6 +435,8 @@ pub struct FilterSet {
     /// Indicates whether or not candidates are accepted even if they
     /// don't match any filters.
     pub accept_unmatched: bool,
+    /// The compiled filters, once compiled. None if the set isn't worth compiling.
+    compiled: OnceLock<Option<CompiledFilters>>,
 }
 
 impl FilterSet {
@@ -101,43 +445,115 @@ impl FilterSet {
         FilterSet {
             filters: Vec::new(),
             accept_unmatched: false,
+            compiled: OnceLock::new(),
         }
     }
 
-    /// Returns whether or not the file path passes all filters.
+    /// Returns the ordered list of filters.
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    /// Returns whether or not the file path passes all filters. Metadata patterns
+    /// never match, since only the path is known.
     pub fn is_acceptable<P: AsRef<Path>>(&self, path: P) -> bool {
-        let path = path.as_ref();
-        for filter in &self.filters {
-            match filter.test(path) {
-                Some(FilterAction::Accept) => return true,
-                Some(FilterAction::Reject) => return false,
-                None => continue,
-            };
+        self.accepts(&Candidate::new(path.as_ref()))
+    }
+
+    /// Returns whether or not the candidate passes all filters.
+    pub fn accepts(&self, candidate: &Candidate) -> bool {
+        let compiled = match self.compiled.get_or_init(|| CompiledFilters::new(&self.filters)) {
+            Some(compiled) => compiled,
+            None => return self.test_each(&self.filters, candidate).unwrap_or(self.accept_unmatched),
+        };
+
+        // Only filters before the first compiled match can take precedence over it, and
+        // of those, only the filters that weren't compiled could match.
+        let first_match = compiled.first_match(candidate.path());
+        let preceding = &self.filters[..first_match.unwrap_or(self.filters.len())];
+        let uncompiled = preceding
+            .iter()
+            .zip(&compiled.compiled)
+            .filter(|(_, &compiled)| !compiled)
+            .map(|(filter, _)| filter);
+        if let Some(accepted) = self.test_each(uncompiled, candidate) {
+            return accepted;
+        }
+        match first_match {
+            Some(index) => matches!(self.filters[index].action, FilterAction::Accept),
+            None => self.accept_unmatched,
         }
-        self.accept_unmatched
+    }
+
+    /// Explains whether or not the candidate passes all filters, by finding the first
+    /// filter that matches it.
+    pub fn explain(&self, candidate: &Candidate) -> FilterExplanation {
+        let filter = self
+            .filters
+            .iter()
+            .enumerate()
+            .find(|(_, filter)| filter.test_candidate(candidate).is_some())
+            .map(|(index, filter)| (index, filter.clone()));
+        FilterExplanation {
+            accepted: filter.as_ref().map_or(self.accept_unmatched, |(_, filter)| {
+                matches!(filter.action, FilterAction::Accept)
+            }),
+            filter,
+        }
+    }
+
+    /// Tests the candidate against each filter in turn, returning whether or not the first
+    /// matching filter accepts it, or None if no filter matches.
+    fn test_each<'a, I>(&self, filters: I, candidate: &Candidate) -> Option<bool>
+    where
+        I: IntoIterator<Item = &'a Filter>,
+    {
+        filters.into_iter().find_map(|filter| match filter.test_candidate(candidate)? {
+            FilterAction::Accept => Some(true),
+            FilterAction::Reject => Some(false),
+        })
     }
 
     /// Creates and appends a filter with the given action.
     pub fn append(&mut self, pattern: FilterPattern, action: FilterAction) {
-        self.filters.push(Filter { pattern, action })
+        self.push(Filter::new(pattern, action));
     }
 
     /// Creates and appends a filter that matches the provided regex with
-    /// the given action.
+    /// the given action. Since the regex may have been built with flags that its
+    /// pattern string doesn't include, it's never compiled with the rest of the set.
     pub fn append_regex(&mut self, pattern: regex::Regex, action: FilterAction) {
-        self.filters.push(Filter {
-            pattern: FilterPattern::Regex(pattern),
-            action,
-        })
+        self.append(FilterPattern::Regex(pattern), action)
+    }
+
+    /// Creates and appends a filter that matches the regex parsed from pattern with
+    /// the given action. Any flags must be given inline, e.g. `(?i)`, which allows
+    /// the regex to be compiled with the rest of the set.
+    pub fn append_regex_str(&mut self, pattern: &str, action: FilterAction) -> Result<(), regex::Error> {
+        let regex = regex::Regex::new(pattern)?;
+        self.push(Filter {
+            exact_regex: true,
+            ..Filter::new(FilterPattern::Regex(regex), action)
+        });
+        Ok(())
+    }
+
+    /// Appends the filter, discarding the compiled filters.
+    fn push(&mut self, filter: Filter) {
+        self.filters.push(filter);
+        self.compiled = OnceLock::new();
     }
 
     /// Creates and appends a filter that matches the provided glob with
     /// the given action.
     pub fn append_glob(&mut self, pattern: glob::Pattern, action: FilterAction) {
-        self.filters.push(Filter {
-            pattern: FilterPattern::Glob(pattern),
-            action,
-        })
+        self.append_glob_with(pattern, GlobOptions::default(), action)
+    }
+
+    /// Creates and appends a filter that matches the provided glob using the
+    /// given match options, with the given action.
+    pub fn append_glob_with(&mut self, pattern: glob::Pattern, options: GlobOptions, action: FilterAction) {
+        self.append(FilterPattern::Glob(pattern, options), action)
     }
 }
 
@@ -250,4 +666,142 @@ mod tests {
 
         assert!(!filter_set.is_acceptable(path));
     }
+
+    #[test]
+    fn glob_match_options() {
+        let mut filter_set = FilterSet::new();
+        let options = GlobOptions {
+            case_sensitive: false,
+            literal_separator: true,
+            literal_leading_dot: true,
+        };
+        filter_set.append_glob_with(glob::Pattern::from_str("*/*.flac").unwrap(), options, FilterAction::Accept);
+
+        assert!(filter_set.is_acceptable("Album/TRACK01.FLAC"));
+        assert!(!filter_set.is_acceptable("Artist/Album/01.flac"));
+        assert!(!filter_set.is_acceptable("Album/.hidden.flac"));
+    }
+
+    #[test]
+    fn compiled_sets_keep_first_match() {
+        let globs = ["*.flac", "Podcasts/*", "*/Live/*", "*.{mp3,ogg}", "**/*.wav", "*.LOG", "a/*/c", "[!x]*.txt"];
+        let regexes = ["^Podcasts/", r"\.jpe?g$", "(?i)cover", "[0-9]{2} - "];
+        let paths = [
+            "Artist/Album/01 - Track.flac",
+            "Podcasts/Show/01.mp3",
+            "Podcasts/Show/01.flac",
+            "Artist/Live/01.ogg",
+            "Artist/Album/COVER.png",
+            "Artist/Album/rip.log",
+            "Artist/Album/rip.LOG",
+            "a/b/c",
+            "a/b/d/c",
+            "notes.txt",
+            "xnotes.txt",
+            "Artist/Album/folder.jpg",
+            "Artist/Album/01.wav",
+            "Artist/Album/01.mp3",
+        ];
+
+        let mut filter_set = FilterSet::new();
+        filter_set.accept_unmatched = true;
+        for (index, glob) in globs.iter().enumerate() {
+            let action = if index % 3 == 0 { FilterAction::Reject } else { FilterAction::Accept };
+            let options = GlobOptions { case_sensitive: index % 2 == 0, ..GlobOptions::default() };
+            filter_set.append_glob_with(glob::Pattern::from_str(glob).unwrap(), options, action);
+            let action = if index % 2 == 0 { FilterAction::Accept } else { FilterAction::Reject };
+            filter_set.append_regex_str(regexes[index % regexes.len()], action).unwrap();
+        }
+        filter_set.append(FilterPattern::Not(Box::new(FilterPattern::Size { min: None, max: None })), FilterAction::Reject);
+
+        assert!(CompiledFilters::new(&filter_set.filters).is_some());
+        for path in paths {
+            let expected = filter_set.test_each(&filter_set.filters, &Candidate::new(Path::new(path)));
+            assert_eq!(filter_set.is_acceptable(path), expected.unwrap_or(true), "{}", path);
+        }
+    }
+
+    #[test]
+    fn compiling_keeps_classes_and_regex_flags() {
+        let paths = ["notes.txt", "xnotes.txt", "^notes.txt", "COVER.jpg", "cover.jpg", "a.flac"];
+        let mut filter_set = FilterSet::new();
+        filter_set.append_glob(glob::Pattern::from_str("[^x]*.txt").unwrap(), FilterAction::Reject);
+        let cover = regex::RegexBuilder::new("^cover").case_insensitive(true).build().unwrap();
+        filter_set.append_regex(cover, FilterAction::Accept);
+        for glob in ["*.flac", "*.mp3", "*.ogg", "*.wav", "*.m4a", "*.opus", "*.aac", "*.wma"] {
+            filter_set.append_glob(glob::Pattern::from_str(glob).unwrap(), FilterAction::Accept);
+        }
+        filter_set.append_regex_str("(?i)\\.txt$", FilterAction::Accept).unwrap();
+
+        let compiled = CompiledFilters::new(&filter_set.filters).unwrap();
+        assert_eq!(compiled.compiled.iter().filter(|&&compiled| compiled).count(), 9);
+        for path in paths {
+            let expected = filter_set.test_each(&filter_set.filters, &Candidate::new(Path::new(path)));
+            assert_eq!(filter_set.is_acceptable(path), expected.unwrap_or(false), "{}", path);
+        }
+        // The glob crate reads `[^x]` as either `^` or `x`, unlike glob sets.
+        assert!(filter_set.is_acceptable("notes.txt"));
+        assert!(!filter_set.is_acceptable("xnotes.txt"));
+        assert!(!filter_set.is_acceptable("^notes.txt"));
+        assert!(filter_set.is_acceptable("COVER.jpg"));
+    }
+
+    #[test]
+    fn composite_patterns() {
+        let glob = |str| FilterPattern::Glob(glob::Pattern::from_str(str).unwrap(), GlobOptions::default());
+        let mut filter_set = FilterSet::new();
+        filter_set.append(
+            FilterPattern::All(vec![
+                glob("Classical/*"),
+                FilterPattern::Any(vec![glob("*.flac"), glob("*.wav")]),
+                FilterPattern::Not(Box::new(glob("*/Live/*"))),
+            ]),
+            FilterAction::Accept,
+        );
+
+        assert!(filter_set.is_acceptable("Classical/Bach/01.flac"));
+        assert!(filter_set.is_acceptable("Classical/Bach/01.wav"));
+        assert!(!filter_set.is_acceptable("Classical/Bach/01.mp3"));
+        assert!(!filter_set.is_acceptable("Classical/Bach/Live/01.flac"));
+        assert!(!filter_set.is_acceptable("Jazz/Monk/01.flac"));
+    }
+
+    #[test]
+    fn metadata_patterns() {
+        let test_dir = std::env::temp_dir().join("condenser-metadata-filter-test");
+        std::fs::create_dir_all(&test_dir).unwrap();
+        std::fs::write(test_dir.join("small.flac"), [0; 10]).unwrap();
+        std::fs::write(test_dir.join("large.flac"), [0; 1000]).unwrap();
+        let small = Path::new("small.flac");
+        let large = Path::new("large.flac");
+
+        let size = FilterPattern::Size { min: None, max: Some(100) };
+        let recent = FilterPattern::Age { min: None, max: Some(Duration::from_secs(3600)) };
+        let old = FilterPattern::Modified { after: None, before: Some(SystemTime::now() - Duration::from_secs(3600)) };
+        let results = [
+            size.matches(&Candidate::in_dir(&test_dir, small)),
+            size.matches(&Candidate::in_dir(&test_dir, large)),
+            size.matches(&Candidate::new(small)),
+            recent.matches(&Candidate::in_dir(&test_dir, large)),
+            old.matches(&Candidate::in_dir(&test_dir, large)),
+        ];
+
+        std::fs::remove_dir_all(&test_dir).unwrap();
+        assert_eq!(results, [true, false, false, true, false]);
+    }
+
+    #[test]
+    fn nested_set_pattern() {
+        let mut nested = FilterSet::new();
+        nested.accept_unmatched = true;
+        nested.append_glob(glob::Pattern::from_str("*.log").unwrap(), FilterAction::Reject);
+
+        let mut filter_set = FilterSet::new();
+        filter_set.append_regex(Regex::from_str("^Podcasts/").unwrap(), FilterAction::Reject);
+        filter_set.append(FilterPattern::Set(nested), FilterAction::Accept);
+
+        assert!(filter_set.is_acceptable("Music/01.flac"));
+        assert!(!filter_set.is_acceptable("Music/rip.log"));
+        assert!(!filter_set.is_acceptable("Podcasts/01.mp3"));
+    }
 }
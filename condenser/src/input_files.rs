@@ -4,7 +4,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::filters::FilterSet;
+use crate::filters::{Candidate, FilterSet};
 
 pub struct InputDirectory {
     /// The priority of this input - higher priorities are favored in conflict resolution.
@@ -19,15 +19,16 @@ pub struct InputDirectory {
 
 impl InputDirectory {
     /// Enumerates all the files under this input directory and returns a Vec
-    /// with all relative paths that match the filters.
-    pub fn enumerate_files(&self) -> Result<Vec<PathBuf>, Error> {
+    /// with a candidate for each relative path that matches the filters.
+    pub fn enumerate_files(&self) -> Result<Vec<Candidate<'_>>, Error> {
         let mut vec = Vec::new();
         self.recurse_dir(&self.path, &mut vec)?;
         Ok(vec)
     }
 
-    /// Recursively enumerates over this directory, pushing relative acceptable paths to vec.
-    fn recurse_dir<'a>(&'a self, dir_path: &Path, vec: &mut Vec<PathBuf>) -> Result<(), Error> {
+    /// Recursively enumerates over this directory, pushing candidates for relative acceptable
+    /// paths to vec.
+    fn recurse_dir<'a>(&'a self, dir_path: &Path, vec: &mut Vec<Candidate<'a>>) -> Result<(), Error> {
         let dir = read_dir(dir_path)?;
         for entry in dir {
             let entry = entry?;
@@ -46,8 +47,9 @@ impl InputDirectory {
             // should always return ok.
             let relative = relative.expect("Expected path to be relative to self.path");
 
-            if self.filters.is_acceptable(relative) {
-                vec.push(relative.to_path_buf())
+            let candidate = Candidate::in_dir(&self.path, relative.to_path_buf());
+            if self.filters.accepts(&candidate) {
+                vec.push(candidate)
             }
         }
         Ok(())
@@ -1,44 +1,142 @@
+mod executor;
+mod explain;
 mod filters;
 mod input_files;
+mod lint;
+mod output_files;
+mod plan;
+mod serde_paths;
+mod sniff;
+mod state;
+#[cfg(test)]
+mod test_fixtures;
 mod transformer;
 
-use std::collections::HashSet;
-use std::fs::read_dir;
+use std::collections::HashMap;
 use std::path::Path;
-use std::path::PathBuf;
 
 use log::debug;
 use log::error;
 use log::info;
 use log::warn;
 
+pub use crate::executor::*;
+pub use crate::explain::*;
 pub use crate::filters::*;
 pub use crate::input_files::*;
+pub use crate::lint::*;
+pub use crate::output_files::*;
+pub use crate::plan::*;
+pub use crate::sniff::*;
+pub use crate::state::*;
 pub use crate::transformer::*;
 
-// TODO: Create an object to hold this data.
-
 /// Runs transformations on the provided input directories using the provided
 /// transformers, outputing to the directory specified by output_dir_path.
+/// Orphans are deleted according to orphan_policy, and the run is aborted if its
+/// deletion limits are exceeded or the output directory isn't managed by condenser.
+/// An empty output directory is marked as managed. Up to jobs files are transformed concurrently.
+/// Returns the number of files that failed to transform.
 pub fn run_transformations<'a, DirIter, P>(
-    transformers: &mut Vec<TransformerInstance>,
+    transformers: &mut [TransformerInstance],
     input_dirs: DirIter,
     output_dir_path: P,
-) where
+    orphan_policy: &OrphanPolicy,
+    jobs: usize,
+) -> Result<usize, std::io::Error>
+where
+    DirIter: IntoIterator<Item = &'a InputDirectory>,
+    P: AsRef<Path>,
+{
+    let plan = plan_transformations(transformers, input_dirs, output_dir_path)?;
+
+    // Ensure the output directory exists
+    if let Err(err) = std::fs::create_dir_all(&plan.output_dir) {
+        error!(
+            "Failed to create output directory: {}",
+            plan.output_dir.to_string_lossy()
+        );
+        return Err(err);
+    }
+    if !mark_if_empty(&plan.output_dir)? {
+        debug!("Output directory isn't marked as managed by condenser");
+    }
+
+    info!("Deleting orphaned files...");
+    // Delete any orphans from the output directory
+    match delete_orphans(&plan, orphan_policy) {
+        Ok(count) => info!("Removed {} orphaned file(s)", count),
+        Err(err) if is_refusal(&err) => {
+            error!("Aborting: {}", err);
+            return Err(err);
+        }
+        Err(err) => warn!("Failed to delete orphaned files: '{}'. Transformations will continue.", err),
+    }
+    match prune_empty_dirs(&plan.output_dir, orphan_policy) {
+        Ok(count) => info!("Removed {} empty director(ies)", count),
+        Err(err) => warn!("Failed to remove empty directories: '{}'. Transformations will continue.", err),
+    }
+
+    execute_plan(&plan, transformers, jobs)
+}
+
+/// Returns whether or not orphan deletion failed because it was refused, rather than
+/// because of a file system error.
+fn is_refusal(err: &std::io::Error) -> bool {
+    err.get_ref()
+        .map_or(false, |err| {
+            err.is::<DeletionLimitError>() || err.is::<UnmanagedDirectoryError>() || err.is::<TrashDirectoryError>()
+        })
+}
+
+/// Enumerates the provided input directories, determines which transformer claims each
+/// file and finds any orphaned files in the output directory. Transformers are sorted by
+/// priority, and the plan refers to them by their index in the sorted slice. Fails if the
+/// output directory overlaps any input directory. Nothing is written to the disk.
+pub fn plan_transformations<'a, DirIter, P>(
+    transformers: &mut [TransformerInstance],
+    input_dirs: DirIter,
+    output_dir_path: P,
+) -> Result<Plan, std::io::Error>
+where
     DirIter: IntoIterator<Item = &'a InputDirectory>,
     P: AsRef<Path>,
 {
     // Ensure that transformers are sorted by priority, highest first.
     transformers.sort_by(|t1, t2| t1.priority.cmp(&t2.priority).reverse());
-    let output_dir_path = output_dir_path.as_ref();
-    let mut claimed_outputs = HashSet::new();
-    let mut output_paths = HashSet::new();
 
-    // Enumerate all input directories and enqueue each matching file with its transformer.
-    // TODO: Might be better to have the queue separate from the TransformerInstance object.
+    // Input directories are likewise sorted by priority, with ties broken by path so
+    // that the outcome doesn't depend on the order they were provided in.
+    let mut input_dirs: Vec<&InputDirectory> = input_dirs.into_iter().collect();
+    input_dirs.sort_by(|d1, d2| d1.priority.cmp(&d2.priority).reverse().then_with(|| d1.path.cmp(&d2.path)));
+    check_output_dir(output_dir_path.as_ref(), input_dirs.iter().copied())?;
+
+    let mut plan = Plan::new(output_dir_path.as_ref().to_path_buf());
+    plan.transformers = transformers
+        .iter()
+        .enumerate()
+        .map(|(index, transformer)| TransformerPlan {
+            transformer: index,
+            name: transformer.name.clone(),
+            claims: Vec::new(),
+        })
+        .collect();
+    let mut claimed_outputs = HashMap::new();
+
+    // Enumerate all input directories and claim each matching file with its transformer.
     for input_dir in input_dirs {
         let input_path = &input_dir.path;
-        let mut unprocessed_files = input_dir.enumerate_files().unwrap();
+        let mut unprocessed_files = match input_dir.enumerate_files() {
+            Ok(files) => files,
+            Err(err) => {
+                error!(
+                    "Failed to enumerate files in {}: {}",
+                    input_path.to_string_lossy(),
+                    err
+                );
+                continue;
+            }
+        };
 
         info!(
             "Beginning to claim {1} files in {0}",
@@ -46,9 +144,15 @@ pub fn run_transformations<'a, DirIter, P>(
             unprocessed_files.len()
         );
 
-        for transformer in transformers.iter_mut() {
-            let count =
-                transformer.claim_outputs(input_path, &mut unprocessed_files, &mut claimed_outputs, &mut output_paths);
+        let mut blocked = HashMap::new();
+        for (transformer, transformer_plan) in transformers.iter().zip(&mut plan.transformers) {
+            let count = transformer.claim_outputs(
+                input_path,
+                &mut unprocessed_files,
+                &mut claimed_outputs,
+                &mut transformer_plan.claims,
+                &mut blocked,
+            );
             info!(
                 "  Transformer '{}' claimed {} files - {} remaining ",
                 &transformer.name,
@@ -61,64 +165,91 @@ pub fn run_transformations<'a, DirIter, P>(
             "{0} files unclaimed by transformers",
             unprocessed_files.len()
         );
+        for candidate in &unprocessed_files {
+            if let Some(conflict) = blocked.remove(candidate.path()) {
+                debug!(
+                    "  {} lost output '{}' to {}",
+                    conflict.loser.input_dir.join(&conflict.loser.file_path).to_string_lossy(),
+                    conflict.output_id.0.to_string_lossy(),
+                    conflict.winner.input_dir.join(&conflict.winner.file_path).to_string_lossy()
+                );
+                plan.conflicts.push(conflict);
+            }
+        }
+        plan.unclaimed.extend(unprocessed_files.into_iter().map(|candidate| UnclaimedInput {
+            input_dir: input_path.clone(),
+            file_path: candidate.into_path(),
+        }));
     }
 
-    // Ensure the output directory exists
-    std::fs::create_dir_all(output_dir_path).unwrap_or_else(|_| {
-        error!(
-            "Failed to create output directory: {}",
-            output_dir_path.to_string_lossy()
-        );
-        return;
-    });
+    let (orphans, output_file_count) = find_orphans(&plan.output_dir, &plan.output_paths())?;
+    plan.orphans = orphans;
+    plan.output_file_count = output_file_count;
+    Ok(plan)
+}
 
-    info!("Deleting orphaned files...");
-    // Delete any orphans from the output directory
-    if let Err(err) = delete_orphans(output_dir_path, output_dir_path, &output_paths) {
-        warn!("Failed to delete orphaned files: '{}'. Transformations will continue.", err)
-    }
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
 
-    // Run the tranformers - this can potentially be done in parallel for each transformer,
-    // since they should be independent from each other.
-    info!("Running {} transformer(s)...", transformers.len());
-    for transformer in transformers {
-        let errors = transformer.process_queues(output_dir_path);
-        info!(
-            "Transformer '{}' processing completed - {} error(s)",
-            &transformer.name,
-            errors.len()
-        );
-        for error in errors {
-            warn!(
-                "Transformer {} encountered an error transforming '{}' - {}",
-                &transformer.name,
-                error.0.to_string_lossy(),
-                error.1
-            )
-        }
-    }
-}
+    use super::*;
+    use crate::test_fixtures::{copy, input_dir};
 
-fn delete_orphans(root_dir: &Path, current_dir: &Path, allowed_files: &HashSet<PathBuf>) -> Result<(), std::io::Error> {
-    // TODO: This needs a lot more configuration options.
-    for entry in read_dir(current_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            delete_orphans(root_dir, &path, allowed_files)?;
-            continue;
-        } else if !path.is_file() {
-            // Skip things that aren't files and aren't paths
-            continue;
+    #[test]
+    fn priorities_decide_claims() {
+        let test_dir = std::env::temp_dir().join("condenser-plan-test");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        for file in ["high/song.flac", "low/song.flac", "tie_b/other.flac", "tie_a/other.flac"] {
+            let path = test_dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
         }
+        let mut transformers = [
+            copy("Low", 10, &["*.flac"]),
+            copy("First", 50, &["*.flac"]),
+            copy("Second", 50, &["*.flac"]),
+        ];
+        let input_dirs = [
+            input_dir(test_dir.join("low"), 10),
+            input_dir(test_dir.join("tie_b"), 50),
+            input_dir(test_dir.join("high"), 100),
+            input_dir(test_dir.join("tie_a"), 50),
+        ];
+        let plan = plan_transformations(&mut transformers, &input_dirs, test_dir.join("out")).unwrap();
+        std::fs::remove_dir_all(&test_dir).unwrap();
 
-        if let Ok(relative_path) = path.strip_prefix(root_dir) {
-            // Skip any files not matching the root prefix
-            if !allowed_files.contains(relative_path) {
-                debug!("Deleting {}", relative_path.to_string_lossy());
-                std::fs::remove_file(path)?;
-            }
-        }
+        // Transformers sharing a priority keep the order they're configured in.
+        let names: Vec<&str> = plan.transformers.iter().map(|transformer| transformer.name.as_str()).collect();
+        assert_eq!(names, ["First", "Second", "Low"]);
+        let claimed: Vec<(&Path, &Path)> = plan
+            .claims()
+            .map(|(transformer, claim)| {
+                assert_eq!(transformer.name, "First");
+                (claim.input_dir.strip_prefix(&test_dir).unwrap(), claim.file_path.as_path())
+            })
+            .collect();
+        assert_eq!(
+            claimed,
+            [(Path::new("high"), Path::new("song.flac")), (Path::new("tie_a"), Path::new("other.flac"))]
+        );
+
+        // Input directories sharing a priority are ordered by path, and the losers are reported.
+        let mut losers: Vec<(PathBuf, PathBuf)> = plan
+            .conflicts
+            .iter()
+            .map(|conflict| {
+                assert_eq!(conflict.winner.transformer, "First");
+                (conflict.loser.input_dir.clone(), conflict.winner.input_dir.clone())
+            })
+            .collect();
+        losers.sort();
+        assert_eq!(
+            losers,
+            [
+                (test_dir.join("low"), test_dir.join("high")),
+                (test_dir.join("tie_b"), test_dir.join("tie_a")),
+            ]
+        );
+        assert_eq!(plan.unclaimed.len(), 2);
     }
-    Ok(())
 }
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use crate::filters::{FilterAction, FilterPattern, FilterSet};
+use crate::input_files::InputDirectory;
+use crate::transformer::TransformerInstance;
+
+/// A likely mistake in a configuration that is nonetheless valid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lint {
+    /// Several transformers share a priority, so the order they claim files in depends
+    /// on the order they're configured in.
+    DuplicatePriority {
+        priority: u32,
+        /// The display names of the transformers, in the order they're configured in.
+        transformers: Vec<String>,
+    },
+
+    /// Every file the transformer accepts is claimed by a higher priority transformer
+    /// first, so it only claims files whose outputs that transformer was blocked from.
+    ShadowedTransformer { transformer: String, shadowed_by: String },
+
+    /// The input directory's filters accept files with the extension, but no
+    /// transformer's filters do.
+    UnhandledExtension { input_dir: PathBuf, extension: String },
+
+    /// The transformer reported a problem with its own configuration.
+    Transformer { transformer: String, message: String },
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lint::DuplicatePriority { priority, transformers } => write!(
+                f,
+                "transformers '{}' share priority {}, so the order they claim files in depends on the order they're configured in",
+                transformers.join("', '"),
+                priority
+            ),
+            Lint::ShadowedTransformer { transformer, shadowed_by } => write!(
+                f,
+                "transformer '{}' is shadowed by '{}', which claims every file it accepts first",
+                transformer, shadowed_by
+            ),
+            Lint::UnhandledExtension { input_dir, extension } => write!(
+                f,
+                "input directory {} accepts '.{}' files, but no transformer does",
+                input_dir.to_string_lossy(),
+                extension
+            ),
+            Lint::Transformer { transformer, message } => write!(f, "transformer '{}': {}", transformer, message),
+        }
+    }
+}
+
+/// Finds likely mistakes in the configuration of the transformers and input directories.
+/// Only the configuration is inspected - no files are read. Shadowing and extensions are
+/// found conservatively from the glob and regex patterns, so not every mistake is found.
+pub fn lint<'a, DirIter>(transformers: &[TransformerInstance], input_dirs: DirIter) -> Vec<Lint>
+where
+    DirIter: IntoIterator<Item = &'a InputDirectory>,
+{
+    let mut lints = Vec::new();
+
+    let mut priorities: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+    for transformer in transformers {
+        priorities.entry(transformer.priority).or_default().push(transformer.name.clone());
+    }
+    lints.extend(priorities.into_iter().rev().filter(|(_, names)| names.len() > 1).map(
+        |(priority, transformers)| Lint::DuplicatePriority { priority, transformers },
+    ));
+
+    for transformer in transformers {
+        let shadowed_by = transformers.iter().find(|other| {
+            other.priority > transformer.priority
+                && other.transformer.handles_all()
+                && covers(&other.filter, &transformer.filter)
+        });
+        if let Some(shadowed_by) = shadowed_by {
+            lints.push(Lint::ShadowedTransformer {
+                transformer: transformer.name.clone(),
+                shadowed_by: shadowed_by.name.clone(),
+            });
+        }
+        lints.extend(transformer.transformer.lint().into_iter().map(|message| Lint::Transformer {
+            transformer: transformer.name.clone(),
+            message,
+        }));
+    }
+
+    for input_dir in input_dirs {
+        for extension in accepted_extensions(&input_dir.filters) {
+            let example = format!("example.{}", extension);
+            if !transformers.iter().any(|transformer| transformer.filter.is_acceptable(&example)) {
+                lints.push(Lint::UnhandledExtension {
+                    input_dir: input_dir.path.clone(),
+                    extension,
+                });
+            }
+        }
+    }
+    lints
+}
+
+/// Tests whether or not set accepts every file that other accepts. Only identical globs
+/// and regexes are compared, so this may return false even if set does cover other.
+fn covers(set: &FilterSet, other: &FilterSet) -> bool {
+    let mut accepted = Vec::new();
+    for filter in set.filters() {
+        match filter.action() {
+            FilterAction::Accept => accepted.push(filter.pattern().to_string()),
+            // Files matching neither a prior accept nor this filter aren't covered.
+            FilterAction::Reject => break,
+        }
+    }
+    let rejects = set.filters().len() != accepted.len();
+    if set.accept_unmatched && !rejects {
+        return true;
+    }
+    !other.accept_unmatched
+        && other.filters().iter().any(|filter| matches!(filter.action(), FilterAction::Accept))
+        && other
+            .filters()
+            .iter()
+            .filter(|filter| matches!(filter.action(), FilterAction::Accept))
+            .all(|filter| accepted.contains(&filter.pattern().to_string()))
+}
+
+/// Finds the file extensions accepted by globs such as `*.flac` and regexes such as
+/// `\.flac$`, including those in nested sets, in the order they appear in the set.
+fn accepted_extensions(set: &FilterSet) -> Vec<String> {
+    let mut extensions: Vec<String> = Vec::new();
+    for filter in set.filters() {
+        if !matches!(filter.action(), FilterAction::Accept) {
+            continue;
+        }
+        let found = match filter.pattern() {
+            FilterPattern::Glob(glob, _) => glob.as_str().rsplit_once("*.").map(|(_, ext)| vec![ext.to_string()]),
+            FilterPattern::Regex(regex) => regex
+                .as_str()
+                .strip_suffix('$')
+                .and_then(|pattern| pattern.rsplit_once("\\."))
+                .map(|(_, ext)| vec![ext.to_string()]),
+            FilterPattern::Set(set) => Some(accepted_extensions(set)),
+            _ => None,
+        };
+        for extension in found.unwrap_or_default() {
+            if !extension.is_empty()
+                && extension.chars().all(|c| c.is_ascii_alphanumeric())
+                && !extensions.iter().any(|known| known.eq_ignore_ascii_case(&extension))
+            {
+                extensions.push(extension);
+            }
+        }
+    }
+    extensions
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::test_fixtures::copy;
+
+    #[test]
+    fn finds_shadowed_transformers_and_unhandled_extensions() {
+        let transformers = [
+            copy("Images", 100, &["*.jpg", "*.png"]),
+            copy("Covers", 50, &["*.jpg"]),
+            copy("Audio", 50, &["*.flac"]),
+        ];
+        let mut input_dir = InputDirectory {
+            priority: 100,
+            filters: FilterSet::new(),
+            path: Path::new("/music").to_path_buf(),
+        };
+        input_dir.filters.append_glob(glob::Pattern::new("*.flac").unwrap(), FilterAction::Accept);
+        input_dir.filters.append_regex(regex::Regex::new("\\.mp3$").unwrap(), FilterAction::Accept);
+
+        let lints = lint(&transformers, [&input_dir]);
+        assert_eq!(
+            lints,
+            [
+                Lint::DuplicatePriority {
+                    priority: 50,
+                    transformers: vec!["Covers".to_string(), "Audio".to_string()]
+                },
+                Lint::ShadowedTransformer {
+                    transformer: "Covers".to_string(),
+                    shadowed_by: "Images".to_string()
+                },
+                Lint::UnhandledExtension {
+                    input_dir: Path::new("/music").to_path_buf(),
+                    extension: "mp3".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,619 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs::read_dir;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+
+use crate::filters::{Candidate, FilterSet};
+use crate::input_files::InputDirectory;
+use crate::plan::Plan;
+
+/// The name of the directory condenser keeps its own files in, located in the root
+/// of the output directory. It is never considered to contain orphans.
+pub const STATE_DIR_NAME: &str = ".condenser";
+
+/// The name of the directory temporary outputs are written to, within the state directory.
+const TEMP_DIR_NAME: &str = "tmp";
+
+/// The name of the file marking an output directory as managed by condenser, within the
+/// state directory.
+pub const MARKER_FILE_NAME: &str = "marker";
+
+/// Returns the path to the directory that temporary outputs are written to before
+/// being moved into place.
+pub fn temp_dir_path(output_dir_path: &Path) -> PathBuf {
+    output_dir_path.join(STATE_DIR_NAME).join(TEMP_DIR_NAME)
+}
+
+/// Deletes any temporary outputs left behind by an interrupted run, and ensures that
+/// the temporary directory exists.
+pub fn clean_temp_files(output_dir_path: &Path) -> Result<(), std::io::Error> {
+    let temp_dir = temp_dir_path(output_dir_path);
+    if temp_dir.exists() {
+        debug!("Deleting temporary files in {}", temp_dir.to_string_lossy());
+        std::fs::remove_dir_all(&temp_dir)?;
+    }
+    std::fs::create_dir_all(&temp_dir)
+}
+
+/// Returns whether or not the output directory has been marked as managed by condenser.
+pub fn is_managed(output_dir_path: &Path) -> bool {
+    output_dir_path.join(STATE_DIR_NAME).join(MARKER_FILE_NAME).is_file()
+}
+
+/// Marks the output directory as managed by condenser, allowing orphans to be deleted from it.
+pub fn write_marker(output_dir_path: &Path) -> Result<(), std::io::Error> {
+    let state_dir = output_dir_path.join(STATE_DIR_NAME);
+    std::fs::create_dir_all(&state_dir)?;
+    std::fs::write(
+        state_dir.join(MARKER_FILE_NAME),
+        "This directory is managed by condenser. Files not produced from its inputs will be deleted.\n",
+    )
+}
+
+/// Marks the output directory as managed by condenser if it's empty, other than the state
+/// directory. Returns whether or not the directory is now managed.
+pub fn mark_if_empty(output_dir_path: &Path) -> Result<bool, std::io::Error> {
+    if is_managed(output_dir_path) {
+        return Ok(true);
+    }
+    if !is_empty(output_dir_path)? {
+        return Ok(false);
+    }
+    write_marker(output_dir_path)?;
+    Ok(true)
+}
+
+/// Checks that orphans may be deleted from the output directory, which must either be
+/// managed by condenser or empty.
+pub fn check_ownership(output_dir_path: &Path) -> Result<(), UnmanagedDirectoryError> {
+    if is_managed(output_dir_path) || is_empty(output_dir_path).unwrap_or(false) {
+        Ok(())
+    } else {
+        Err(UnmanagedDirectoryError {
+            output_dir: output_dir_path.to_path_buf(),
+        })
+    }
+}
+
+/// Returns whether or not the directory is missing or contains nothing but the state directory.
+fn is_empty(dir_path: &Path) -> Result<bool, std::io::Error> {
+    let entries = match read_dir(dir_path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        if entry?.file_name() != STATE_DIR_NAME {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Indicates that orphans weren't deleted because the output directory isn't empty and
+/// hasn't been marked as managed by condenser.
+#[derive(Debug, Clone)]
+pub struct UnmanagedDirectoryError {
+    /// The path to the output directory.
+    pub output_dir: PathBuf,
+}
+
+impl Display for UnmanagedDirectoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to delete orphans - {} is not empty and isn't marked as managed by condenser",
+            self.output_dir.to_string_lossy()
+        )
+    }
+}
+
+impl std::error::Error for UnmanagedDirectoryError {}
+
+/// Checks that the output directory neither is, contains nor is contained by any of the
+/// input directories, since orphan deletion would otherwise delete inputs.
+pub fn check_output_dir<'a, DirIter>(output_dir_path: &Path, input_dirs: DirIter) -> Result<(), std::io::Error>
+where
+    DirIter: IntoIterator<Item = &'a InputDirectory>,
+{
+    let output_dir = resolve_path(output_dir_path);
+    for input_dir in input_dirs {
+        let input_path = resolve_path(&input_dir.path);
+        if output_dir.starts_with(&input_path) || input_path.starts_with(&output_dir) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Output directory {} overlaps input directory {}",
+                    output_dir_path.to_string_lossy(),
+                    input_dir.path.to_string_lossy()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the trash directory neither is, contains nor is contained by the output
+/// directory or any of the input directories, since trashed orphans would otherwise
+/// overwrite outputs or inputs, or be enumerated as inputs. The trash directory may be
+/// within the state directory, apart from its temporary directory.
+pub fn check_trash_dir<'a, DirIter>(
+    trash_dir_path: &Path,
+    output_dir_path: &Path,
+    input_dirs: DirIter,
+) -> Result<(), TrashDirectoryError>
+where
+    DirIter: IntoIterator<Item = &'a InputDirectory>,
+{
+    let trash_dir = resolve_path(trash_dir_path);
+    let overlaps = |path: &Path| {
+        let path = resolve_path(path);
+        trash_dir.starts_with(&path) || path.starts_with(&trash_dir)
+    };
+    let error = |directory: &Path| TrashDirectoryError {
+        trash_dir: trash_dir_path.to_path_buf(),
+        directory: directory.to_path_buf(),
+    };
+
+    let state_dir = resolve_path(&output_dir_path.join(STATE_DIR_NAME));
+    let within_state_dir = trash_dir.starts_with(&state_dir) && trash_dir != state_dir;
+    if within_state_dir && overlaps(&temp_dir_path(output_dir_path)) {
+        return Err(error(&temp_dir_path(output_dir_path)));
+    }
+    if !within_state_dir && overlaps(output_dir_path) {
+        return Err(error(output_dir_path));
+    }
+    match input_dirs.into_iter().find(|input_dir| overlaps(&input_dir.path)) {
+        Some(input_dir) => Err(error(&input_dir.path)),
+        None => Ok(()),
+    }
+}
+
+/// Indicates that orphans weren't moved to the trash directory because it overlaps the
+/// output directory or an input directory.
+#[derive(Debug, Clone)]
+pub struct TrashDirectoryError {
+    /// The path to the trash directory.
+    pub trash_dir: PathBuf,
+
+    /// The path to the directory it overlaps.
+    pub directory: PathBuf,
+}
+
+impl Display for TrashDirectoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to trash orphans - trash directory {} overlaps {}",
+            self.trash_dir.to_string_lossy(),
+            self.directory.to_string_lossy()
+        )
+    }
+}
+
+impl std::error::Error for TrashDirectoryError {}
+
+/// Resolves symbolic links in the longest existing prefix of path, appending
+/// the remainder unchanged.
+fn resolve_path(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut remainder = Vec::new();
+    loop {
+        if let Ok(resolved) = std::fs::canonicalize(existing) {
+            return remainder.iter().rev().fold(resolved, |path, name| path.join(name));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                remainder.push(name);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Controls which orphans are deleted, and how.
+#[derive(Clone, Debug, Default)]
+pub struct OrphanPolicy {
+    /// Orphans accepted by this filter set are protected, and never deleted.
+    pub protect: FilterSet,
+
+    /// If set, orphans are moved into this directory, keeping their relative paths,
+    /// instead of being deleted. An orphan whose path is already taken in the trash is
+    /// given a numbered suffix, e.g. `song.1.ogg`. It may not be within the output
+    /// directory, other than within the state directory.
+    pub trash_dir: Option<PathBuf>,
+
+    /// If set, no orphans are deleted if more than this many would be.
+    pub max_deletions: Option<usize>,
+
+    /// If set, no orphans are deleted if more than this percentage of the files
+    /// in the output directory would be.
+    pub max_deletion_percent: Option<f64>,
+}
+
+impl OrphanPolicy {
+    /// Returns whether or not the file at the relative path within the output directory
+    /// is protected from deletion.
+    pub fn is_protected(&self, output_dir_path: &Path, relative_path: &Path) -> bool {
+        self.protect.accepts(&Candidate::in_dir(output_dir_path, relative_path))
+    }
+
+    /// Checks that deleting count out of total files stays within the deletion limits.
+    pub fn check_limits(&self, count: usize, total: usize) -> Result<(), DeletionLimitError> {
+        if let Some(max_deletions) = self.max_deletions {
+            if count > max_deletions {
+                return Err(DeletionLimitError {
+                    count,
+                    total,
+                    limit: format!("{} files", max_deletions),
+                });
+            }
+        }
+        if let Some(max_deletion_percent) = self.max_deletion_percent {
+            if total > 0 && count as f64 * 100.0 / total as f64 > max_deletion_percent {
+                return Err(DeletionLimitError {
+                    count,
+                    total,
+                    limit: format!("{}% of files", max_deletion_percent),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Indicates that deleting orphans was aborted because too many files would be deleted.
+#[derive(Debug, Clone)]
+pub struct DeletionLimitError {
+    /// The number of orphans that would have been deleted.
+    pub count: usize,
+
+    /// The total number of files in the output directory.
+    pub total: usize,
+
+    /// A description of the limit that was exceeded.
+    pub limit: String,
+}
+
+impl Display for DeletionLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Refusing to delete {} of {} files - the limit is {}",
+            self.count, self.total, self.limit
+        )
+    }
+}
+
+impl std::error::Error for DeletionLimitError {}
+
+/// Deletes, or moves to the trash directory, every orphan in the plan that isn't
+/// protected by the policy. Nothing is deleted if doing so would exceed the policy's
+/// deletion limits, if the output directory isn't managed by condenser, or if the trash
+/// directory overlaps the output directory. Returns the number of orphans removed from the output directory.
+pub fn delete_orphans(plan: &Plan, policy: &OrphanPolicy) -> Result<usize, std::io::Error> {
+    let orphans: Vec<&PathBuf> = plan.orphans.iter().filter(|path| !policy.is_protected(&plan.output_dir, path)).collect();
+    let protected_count = plan.orphans.len() - orphans.len();
+    if protected_count > 0 {
+        info!("Keeping {} protected orphan(s)", protected_count);
+    }
+    if orphans.is_empty() {
+        return Ok(0);
+    }
+
+    check_ownership(&plan.output_dir).map_err(|err| std::io::Error::new(ErrorKind::Other, err))?;
+
+    policy
+        .check_limits(orphans.len(), plan.output_file_count)
+        .map_err(|err| std::io::Error::new(ErrorKind::Other, err))?;
+
+    if let Some(trash_dir) = &policy.trash_dir {
+        check_trash_dir(trash_dir, &plan.output_dir, []).map_err(|err| std::io::Error::new(ErrorKind::Other, err))?;
+    }
+
+    for relative_path in &orphans {
+        let path = plan.output_dir.join(relative_path);
+        match &policy.trash_dir {
+            Some(trash_dir) => {
+                debug!("Moving {} to trash", relative_path.to_string_lossy());
+                move_file(&path, &trash_path(trash_dir, relative_path))?;
+            }
+            None => {
+                debug!("Deleting {}", relative_path.to_string_lossy());
+                std::fs::remove_file(path)?;
+            }
+        }
+    }
+    Ok(orphans.len())
+}
+
+/// Removes every empty directory under output_dir_path, including directories that only
+/// contained empty directories. Directories accepted by the policy's protect filters, along
+/// with everything within them, and the state directory are kept. Nothing is removed unless
+/// the output directory is managed by condenser. Returns the number of directories removed.
+pub fn prune_empty_dirs(output_dir_path: &Path, policy: &OrphanPolicy) -> Result<usize, std::io::Error> {
+    let mut count = 0;
+    if is_managed(output_dir_path) {
+        prune_empty_dirs_in(output_dir_path, output_dir_path, policy, &mut count)?;
+    }
+    Ok(count)
+}
+
+/// Prunes the empty directories within current_dir, returning whether or not
+/// current_dir is now empty.
+fn prune_empty_dirs_in(
+    root_dir: &Path,
+    current_dir: &Path,
+    policy: &OrphanPolicy,
+    count: &mut usize,
+) -> Result<bool, std::io::Error> {
+    let mut is_empty = true;
+    for entry in read_dir(current_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type()?.is_dir() || (current_dir == root_dir && entry.file_name() == STATE_DIR_NAME) {
+            is_empty = false;
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(root_dir).unwrap_or(&path);
+        if policy.is_protected(root_dir, relative_path) || !prune_empty_dirs_in(root_dir, &path, policy, count)? {
+            is_empty = false;
+            continue;
+        }
+
+        debug!("Removing empty directory {}", relative_path.to_string_lossy());
+        std::fs::remove_dir(&path)?;
+        *count += 1;
+    }
+    Ok(is_empty)
+}
+
+/// Returns the path within trash_dir to move the orphan at relative_path to, adding a
+/// numbered suffix before its extension if an earlier orphan already took its path.
+fn trash_path(trash_dir: &Path, relative_path: &Path) -> PathBuf {
+    let path = trash_dir.join(relative_path);
+    let stem = path.file_stem().unwrap_or_default().to_os_string();
+    let mut candidate = path.clone();
+    let mut number = 0;
+    while candidate.symlink_metadata().is_ok() {
+        number += 1;
+        let mut file_name = stem.clone();
+        file_name.push(format!(".{}", number));
+        if let Some(extension) = path.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        candidate = path.with_file_name(file_name);
+    }
+    candidate
+}
+
+/// Moves the file at from to to, creating any missing parent directories. Falls back
+/// to copying and deleting if the file can't be renamed, e.g. across file systems.
+fn move_file(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_err() {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Returns the relative path of every file under output_dir_path that is not in allowed_files,
+/// along with the total number of files found. If output_dir_path doesn't exist, no files
+/// are returned.
+pub fn find_orphans(
+    output_dir_path: &Path,
+    allowed_files: &HashSet<&Path>,
+) -> Result<(Vec<PathBuf>, usize), std::io::Error> {
+    let mut orphans = Vec::new();
+    let mut file_count = 0;
+    if output_dir_path.is_dir() {
+        find_orphans_in(output_dir_path, output_dir_path, allowed_files, &mut orphans, &mut file_count)?;
+    }
+    Ok((orphans, file_count))
+}
+
+fn find_orphans_in(
+    root_dir: &Path,
+    current_dir: &Path,
+    allowed_files: &HashSet<&Path>,
+    orphans: &mut Vec<PathBuf>,
+    file_count: &mut usize,
+) -> Result<(), std::io::Error> {
+    for entry in read_dir(current_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            if current_dir == root_dir && entry.file_name() == STATE_DIR_NAME {
+                // Skip condenser's own files
+                continue;
+            }
+            find_orphans_in(root_dir, &path, allowed_files, orphans, file_count)?;
+            continue;
+        } else if !path.is_file() {
+            // Skip things that aren't files and aren't paths
+            continue;
+        }
+
+        *file_count += 1;
+        if let Ok(relative_path) = path.strip_prefix(root_dir) {
+            // Skip any files not matching the root prefix
+            if !allowed_files.contains(relative_path) {
+                orphans.push(relative_path.to_path_buf());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::FilterAction;
+
+    #[test]
+    fn unmanaged_dirs_are_only_marked_if_empty() {
+        let test_dir = std::env::temp_dir().join("condenser-ownership-test");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        let (unmanaged, empty) = (test_dir.join("unmanaged"), test_dir.join("empty"));
+        std::fs::create_dir_all(&unmanaged).unwrap();
+        std::fs::create_dir_all(empty.join(STATE_DIR_NAME)).unwrap();
+        std::fs::write(unmanaged.join("song.ogg"), "").unwrap();
+
+        let unmanaged_owned = check_ownership(&unmanaged);
+        let unmanaged_marked = mark_if_empty(&unmanaged).unwrap();
+        let empty_owned = check_ownership(&empty);
+        let empty_marked = mark_if_empty(&empty).unwrap();
+        std::fs::write(empty.join("song.ogg"), "").unwrap();
+        let marked_owned = check_ownership(&empty);
+        let managed = [&unmanaged, &empty].map(|dir| is_managed(dir));
+        std::fs::remove_dir_all(&test_dir).unwrap();
+
+        assert_eq!(unmanaged_owned.unwrap_err().output_dir, unmanaged);
+        assert!(!unmanaged_marked);
+        assert!(empty_owned.is_ok());
+        assert!(empty_marked);
+        assert!(marked_owned.is_ok());
+        assert_eq!(managed, [false, true]);
+    }
+
+    #[test]
+    fn overlapping_output_dirs_are_rejected() {
+        let test_dir = std::env::temp_dir().join("condenser-overlap-test");
+        let input_dir = |path: &str| InputDirectory {
+            priority: 0,
+            filters: FilterSet::new(),
+            path: test_dir.join(path),
+        };
+        let input_dirs = [input_dir("music/flac"), input_dir("other")];
+
+        assert!(check_output_dir(&test_dir.join("portable"), &input_dirs).is_ok());
+        assert!(check_output_dir(&test_dir.join("music/flac-portable"), &input_dirs).is_ok());
+        for output_dir in ["music/flac", "music/flac/portable", "music"] {
+            let err = check_output_dir(&test_dir.join(output_dir), &input_dirs).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", output_dir);
+        }
+    }
+
+    /// Creates a managed output directory at test_dir containing the files, and a plan
+    /// in which they're all orphans.
+    fn plan_with_orphans(test_dir: &Path, files: &[&str]) -> Plan {
+        let _ = std::fs::remove_dir_all(test_dir);
+        std::fs::create_dir_all(test_dir).unwrap();
+        write_marker(test_dir).unwrap();
+        let mut plan = Plan::new(test_dir.to_path_buf());
+        for file in files {
+            let path = test_dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+            plan.orphans.push(PathBuf::from(file));
+        }
+        plan.output_file_count = files.len();
+        plan
+    }
+
+    #[test]
+    fn deletion_limits_abort_deletion() {
+        let by_count = OrphanPolicy {
+            max_deletions: Some(2),
+            ..Default::default()
+        };
+        let by_percent = OrphanPolicy {
+            max_deletion_percent: Some(50.0),
+            ..Default::default()
+        };
+        assert!(by_count.check_limits(2, 2).is_ok());
+        assert!(by_count.check_limits(3, 100).is_err());
+        assert!(by_percent.check_limits(5, 10).is_ok());
+        assert!(by_percent.check_limits(6, 10).is_err());
+        assert!(by_percent.check_limits(0, 0).is_ok());
+
+        let test_dir = std::env::temp_dir().join("condenser-deletion-limit-test");
+        let mut plan = plan_with_orphans(&test_dir, &["a.ogg", "b.ogg", "c.ogg"]);
+        let count_result = delete_orphans(&plan, &by_count);
+        plan.output_file_count = 4;
+        let percent_result = delete_orphans(&plan, &by_percent);
+        let remaining = plan.orphans.iter().all(|path| test_dir.join(path).exists());
+        std::fs::remove_dir_all(&test_dir).unwrap();
+
+        for result in [count_result, percent_result] {
+            let err = result.unwrap_err();
+            assert!(err.get_ref().is_some_and(|err| err.is::<DeletionLimitError>()), "{}", err);
+        }
+        assert!(remaining);
+    }
+
+    #[test]
+    fn protected_orphans_are_kept() {
+        let mut policy = OrphanPolicy {
+            max_deletions: Some(1),
+            ..Default::default()
+        };
+        policy.protect.append_glob(glob::Pattern::new("Playlists/*").unwrap(), FilterAction::Accept);
+
+        let test_dir = std::env::temp_dir().join("condenser-protect-test");
+        let plan = plan_with_orphans(&test_dir, &["Playlists/a.m3u", "Playlists/b.m3u", "a.ogg"]);
+        let count = delete_orphans(&plan, &policy).unwrap();
+        let remaining = plan.orphans.iter().map(|path| test_dir.join(path).exists()).collect::<Vec<_>>();
+        std::fs::remove_dir_all(&test_dir).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(remaining, [true, true, false]);
+    }
+
+    #[test]
+    fn trashed_orphans_keep_earlier_ones() {
+        let test_dir = std::env::temp_dir().join("condenser-trash-test");
+        let trash_dir = std::env::temp_dir().join("condenser-trash-test-trash");
+        let _ = std::fs::remove_dir_all(&trash_dir);
+        let policy = OrphanPolicy {
+            trash_dir: Some(trash_dir.clone()),
+            ..Default::default()
+        };
+
+        let mut counts = Vec::new();
+        for _ in 0..3 {
+            let plan = plan_with_orphans(&test_dir, &["Artist/song.ogg"]);
+            counts.push(delete_orphans(&plan, &policy).unwrap());
+        }
+        let orphan_exists = test_dir.join("Artist/song.ogg").exists();
+        let trashed = ["song.ogg", "song.1.ogg", "song.2.ogg"]
+            .map(|name| std::fs::read_to_string(trash_dir.join("Artist").join(name)).ok());
+        std::fs::remove_dir_all(&test_dir).unwrap();
+        std::fs::remove_dir_all(&trash_dir).unwrap();
+
+        assert_eq!(counts, [1, 1, 1]);
+        assert!(!orphan_exists);
+        assert!(trashed.iter().all(|contents| contents.as_deref() == Some("Artist/song.ogg")), "{:?}", trashed);
+    }
+
+    #[test]
+    fn prune_empty_dirs_keeps_files_and_protected_dirs() {
+        let test_dir = std::env::temp_dir().join("condenser-prune-test");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        for dir in ["Artist/Empty Album/Disc 1", "Artist/Album", "Playlists", ".condenser/tmp"] {
+            std::fs::create_dir_all(test_dir.join(dir)).unwrap();
+        }
+        std::fs::write(test_dir.join("Artist/Album/01.ogg"), "").unwrap();
+
+        let mut policy = OrphanPolicy::default();
+        policy.protect.append_glob(glob::Pattern::new("Playlists").unwrap(), FilterAction::Accept);
+        write_marker(&test_dir).unwrap();
+        let count = prune_empty_dirs(&test_dir, &policy).unwrap();
+
+        let remaining = ["Artist/Album/01.ogg", "Playlists", ".condenser/tmp"].map(|path| test_dir.join(path).exists());
+        let removed = test_dir.join("Artist/Empty Album").exists();
+        std::fs::remove_dir_all(&test_dir).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(remaining, [true; 3]);
+        assert!(!removed);
+    }
+}
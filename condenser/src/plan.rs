@@ -0,0 +1,177 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::transformer::OutputId;
+
+/// An input file claimed by a transformer, along with the output it will produce.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claim {
+    /// The absolute path to the input directory containing the file.
+    #[serde(with = "crate::serde_paths")]
+    pub input_dir: PathBuf,
+
+    /// The path to the input file, relative to input_dir.
+    #[serde(with = "crate::serde_paths")]
+    pub file_path: PathBuf,
+
+    /// The ID of the output claimed by this input.
+    pub output_id: OutputId,
+
+    /// The path to the output file, relative to the output directory.
+    #[serde(with = "crate::serde_paths")]
+    pub output_path: PathBuf,
+}
+
+impl Claim {
+    /// Returns the absolute path to the input file.
+    pub fn input_path(&self) -> PathBuf {
+        self.input_dir.join(&self.file_path)
+    }
+}
+
+/// The claims made by a single transformer.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransformerPlan {
+    /// The index of the transformer in the (priority sorted) transformer list
+    /// the plan was created from.
+    pub transformer: usize,
+
+    /// The display name of the transformer.
+    pub name: String,
+
+    /// The inputs claimed by the transformer.
+    pub claims: Vec<Claim>,
+}
+
+/// An input file that no transformer claimed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnclaimedInput {
+    /// The absolute path to the input directory containing the file.
+    #[serde(with = "crate::serde_paths")]
+    pub input_dir: PathBuf,
+
+    /// The path to the input file, relative to input_dir.
+    #[serde(with = "crate::serde_paths")]
+    pub file_path: PathBuf,
+}
+
+/// Identifies an input file and the transformer that attempted to claim its output.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimSource {
+    /// The absolute path to the input directory containing the file.
+    #[serde(with = "crate::serde_paths")]
+    pub input_dir: PathBuf,
+
+    /// The path to the input file, relative to input_dir.
+    #[serde(with = "crate::serde_paths")]
+    pub file_path: PathBuf,
+
+    /// The display name of the transformer.
+    pub transformer: String,
+}
+
+/// An input that was left unclaimed because its output had already been claimed
+/// by another input.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conflict {
+    /// The ID of the contested output.
+    pub output_id: OutputId,
+
+    /// The input that claimed the output.
+    pub winner: ClaimSource,
+
+    /// The input that was blocked from claiming the output.
+    pub loser: ClaimSource,
+}
+
+/// Describes every transformation and deletion needed to bring an output directory
+/// up to date, without performing any of them.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    /// The absolute path to the output directory.
+    #[serde(with = "crate::serde_paths")]
+    pub output_dir: PathBuf,
+
+    /// The claims of each transformer, highest priority first.
+    pub transformers: Vec<TransformerPlan>,
+
+    /// The paths of files in the output directory that aren't claimed by any input,
+    /// relative to the output directory.
+    #[serde(with = "crate::serde_paths::list")]
+    pub orphans: Vec<PathBuf>,
+
+    /// The total number of files found in the output directory.
+    pub output_file_count: usize,
+
+    /// The input files that no transformer claimed.
+    pub unclaimed: Vec<UnclaimedInput>,
+
+    /// The unclaimed input files that lost their output to an earlier claim.
+    pub conflicts: Vec<Conflict>,
+
+    /// The relative paths of the outputs of claims removed by retain_claims, which
+    /// remain claimed even though they won't be executed.
+    #[serde(default, with = "crate::serde_paths::list")]
+    pub withheld_outputs: Vec<PathBuf>,
+}
+
+impl Plan {
+    /// Creates an empty plan for the given output directory.
+    pub fn new(output_dir: PathBuf) -> Plan {
+        Plan {
+            output_dir,
+            transformers: Vec::new(),
+            orphans: Vec::new(),
+            output_file_count: 0,
+            unclaimed: Vec::new(),
+            conflicts: Vec::new(),
+            withheld_outputs: Vec::new(),
+        }
+    }
+
+    /// Returns the absolute path to the output file of a claim.
+    pub fn output_path(&self, claim: &Claim) -> PathBuf {
+        self.output_dir.join(&claim.output_path)
+    }
+
+    /// Returns the relative paths of every claimed output, including those withheld by
+    /// retain_claims.
+    pub fn output_paths(&self) -> HashSet<&Path> {
+        self.claims()
+            .map(|(_, claim)| claim.output_path.as_path())
+            .chain(self.withheld_outputs.iter().map(PathBuf::as_path))
+            .collect()
+    }
+
+    /// Returns an iterator over every claim, along with the transformer that made it.
+    pub fn claims(&self) -> impl Iterator<Item = (&TransformerPlan, &Claim)> {
+        self.transformers
+            .iter()
+            .flat_map(|transformer| transformer.claims.iter().map(move |claim| (transformer, claim)))
+    }
+
+    /// Returns the total number of claims across all transformers.
+    pub fn claim_count(&self) -> usize {
+        self.transformers.iter().map(|t| t.claims.len()).sum()
+    }
+
+    /// Retains only the claims for which predicate returns true. Removing a claim
+    /// prevents it from being executed, but does not make its output an orphan, and
+    /// its output's record is kept in the state database.
+    pub fn retain_claims<F>(&mut self, mut predicate: F)
+    where
+        F: FnMut(&TransformerPlan, &Claim) -> bool,
+    {
+        for transformer in &mut self.transformers {
+            let claims = std::mem::take(&mut transformer.claims);
+            let (retained, withheld): (Vec<Claim>, Vec<Claim>) =
+                claims.into_iter().partition(|claim| predicate(transformer, claim));
+            self.withheld_outputs.extend(withheld.into_iter().map(|claim| claim.output_path));
+            transformer.claims = retained;
+        }
+    }
+}
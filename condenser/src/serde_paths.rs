@@ -0,0 +1,100 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An OS string as it's serialized: a string if it's valid Unicode, so that it stays
+/// readable, or the platform's raw OS string otherwise, so that nothing is lost.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredOsString {
+    Unicode(String),
+    Raw(OsString),
+}
+
+impl From<StoredOsString> for OsString {
+    fn from(stored: StoredOsString) -> Self {
+        match stored {
+            StoredOsString::Unicode(str) => str.into(),
+            StoredOsString::Raw(str) => str,
+        }
+    }
+}
+
+/// Serializes an OS string losslessly, as a string if it's valid Unicode.
+pub fn serialize_os_str<S: Serializer>(str: &OsStr, serializer: S) -> Result<S::Ok, S::Error> {
+    match str.to_str() {
+        Some(str) => serializer.serialize_str(str),
+        None => str.serialize(serializer),
+    }
+}
+
+/// Deserializes an OS string serialized by serialize_os_str.
+pub fn deserialize_os_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OsString, D::Error> {
+    StoredOsString::deserialize(deserializer).map(OsString::from)
+}
+
+/// Serializes a path losslessly, as a string if it's valid Unicode. Used with
+/// `#[serde(with = "crate::serde_paths")]`, since paths can't otherwise be serialized
+/// unless they're valid Unicode.
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serialize_os_str(path.as_os_str(), serializer)
+}
+
+/// Deserializes a path serialized by serialize.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    deserialize_os_string(deserializer).map(PathBuf::from)
+}
+
+/// A path that is serialized losslessly.
+pub struct LosslessPath<'a>(pub &'a Path);
+
+impl Serialize for LosslessPath<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(self.0, serializer)
+    }
+}
+
+/// Serializes lists of paths losslessly. Used with `#[serde(with = "crate::serde_paths::list")]`.
+pub mod list {
+    use super::*;
+
+    /// Serializes each of the paths losslessly.
+    pub fn serialize<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(paths.iter().map(|path| LosslessPath(path)))
+    }
+
+    /// Deserializes a list of paths serialized by serialize.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+        let paths = Vec::<StoredOsString>::deserialize(deserializer)?;
+        Ok(paths.into_iter().map(|path| PathBuf::from(OsString::from(path))).collect())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::ffi::OsStrExt;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Paths {
+        #[serde(with = "crate::serde_paths")]
+        path: PathBuf,
+        #[serde(with = "crate::serde_paths::list")]
+        paths: Vec<PathBuf>,
+    }
+
+    #[test]
+    fn paths_round_trip_losslessly() {
+        let invalid = PathBuf::from(OsStr::from_bytes(b"Artist/bad\xe9.flac"));
+        let paths = Paths {
+            path: invalid.clone(),
+            paths: vec![PathBuf::from("Artist/good.flac"), invalid],
+        };
+
+        let json = serde_json::to_string(&paths).unwrap();
+        assert!(json.contains(r#""Artist/good.flac""#), "{}", json);
+        assert_eq!(serde_json::from_str::<Paths>(&json).unwrap(), paths);
+    }
+}
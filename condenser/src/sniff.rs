@@ -0,0 +1,213 @@
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The number of leading bytes read to detect a file's type.
+const HEADER_LEN: usize = 16;
+
+/// The length of an ID3v2 tag header.
+const ID3_HEADER_LEN: usize = 10;
+
+/// A file type that can be detected from a file's leading bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Flac,
+    Mp3,
+    /// AAC in an ADTS stream.
+    Aac,
+    /// Any ISO base media file, such as MP4 or M4A.
+    Mp4,
+    /// Any Ogg container, such as Vorbis or Opus.
+    Ogg,
+    Wav,
+    Aiff,
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Bmp,
+    Tiff,
+}
+
+impl FileType {
+    /// Every detectable file type.
+    pub const ALL: [FileType; 13] = [
+        FileType::Flac,
+        FileType::Mp3,
+        FileType::Aac,
+        FileType::Mp4,
+        FileType::Ogg,
+        FileType::Wav,
+        FileType::Aiff,
+        FileType::Jpeg,
+        FileType::Png,
+        FileType::Gif,
+        FileType::Webp,
+        FileType::Bmp,
+        FileType::Tiff,
+    ];
+
+    /// Returns the lowercase name of the file type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileType::Flac => "flac",
+            FileType::Mp3 => "mp3",
+            FileType::Aac => "aac",
+            FileType::Mp4 => "mp4",
+            FileType::Ogg => "ogg",
+            FileType::Wav => "wav",
+            FileType::Aiff => "aiff",
+            FileType::Jpeg => "jpeg",
+            FileType::Png => "png",
+            FileType::Gif => "gif",
+            FileType::Webp => "webp",
+            FileType::Bmp => "bmp",
+            FileType::Tiff => "tiff",
+        }
+    }
+
+    /// Detects the file type from the leading bytes of a file, returning None if
+    /// the type isn't recognised.
+    pub fn detect(header: &[u8]) -> Option<FileType> {
+        let starts_with = |magic: &[u8]| header.starts_with(magic);
+        let riff = |form: &[u8]| starts_with(b"RIFF") && header.get(8..12) == Some(form);
+        if starts_with(b"fLaC") {
+            Some(FileType::Flac)
+        } else if starts_with(b"OggS") {
+            Some(FileType::Ogg)
+        } else if riff(b"WAVE") {
+            Some(FileType::Wav)
+        } else if riff(b"WEBP") {
+            Some(FileType::Webp)
+        } else if starts_with(b"FORM") && matches!(header.get(8..12), Some(b"AIFF") | Some(b"AIFC")) {
+            Some(FileType::Aiff)
+        } else if header.get(4..8) == Some(b"ftyp") {
+            Some(FileType::Mp4)
+        } else if starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(FileType::Jpeg)
+        } else if starts_with(b"\x89PNG\r\n\x1A\n") {
+            Some(FileType::Png)
+        } else if starts_with(b"GIF87a") || starts_with(b"GIF89a") {
+            Some(FileType::Gif)
+        } else if is_bmp(header) {
+            Some(FileType::Bmp)
+        } else if starts_with(b"II*\0") || starts_with(b"MM\0*") {
+            Some(FileType::Tiff)
+        } else if starts_with(b"ID3") {
+            // Audio following an ID3 tag is assumed to be MP3 if it can't be checked
+            Some(FileType::Mp3)
+        } else {
+            detect_frame(header)
+        }
+    }
+}
+
+/// Detects a BMP file header, whose `BM` signature is too short to rely on alone. The
+/// reserved fields must be zero, the pixel data must start after the smallest info header,
+/// and the file size, if given, must include the pixel data's offset.
+fn is_bmp(header: &[u8]) -> bool {
+    let u32_at = |start: usize| u32::from_le_bytes(header[start..start + 4].try_into().expect("Expected 4 bytes"));
+    if !header.starts_with(b"BM") || header.len() < 14 || u32_at(6) != 0 {
+        return false;
+    }
+    let (size, offset) = (u32_at(2), u32_at(10));
+    offset >= 26 && (size == 0 || size >= offset)
+}
+
+/// Detects MPEG audio and ADTS frame headers, which share a sync word but differ in
+/// the layer bits - AAC always has a layer of 0.
+fn detect_frame(header: &[u8]) -> Option<FileType> {
+    match header {
+        [0xFF, second, ..] if second & 0xF6 == 0xF0 => Some(FileType::Aac),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 && second & 0x06 != 0 => Some(FileType::Mp3),
+        _ => None,
+    }
+}
+
+impl Display for FileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for FileType {
+    type Err = String;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        let lower = str.to_ascii_lowercase();
+        let name = match lower.as_str() {
+            "jpg" => "jpeg",
+            "m4a" => "mp4",
+            "tif" => "tiff",
+            name => name,
+        };
+        FileType::ALL
+            .into_iter()
+            .find(|file_type| file_type.name() == name)
+            .ok_or_else(|| format!("Unknown file type '{}'", str))
+    }
+}
+
+/// Detects the type of the file at path from its leading bytes. Leading ID3 tags are
+/// skipped, so that the audio following them can be identified.
+pub fn sniff_file(path: &Path) -> Result<Option<FileType>, std::io::Error> {
+    let mut file = File::open(path)?;
+    let mut header = read_header(&mut file)?;
+    if header.len() >= ID3_HEADER_LEN && header.starts_with(b"ID3") {
+        // The tag size is a 28 bit "synchsafe" integer, excluding the header and footer.
+        let size = header[6..10].iter().fold(0_u64, |size, byte| (size << 7) | u64::from(byte & 0x7F));
+        let footer_len = if header[5] & 0x10 != 0 { ID3_HEADER_LEN as u64 } else { 0 };
+        file.seek(SeekFrom::Start(ID3_HEADER_LEN as u64 + size + footer_len))?;
+        let audio_header = read_header(&mut file)?;
+        if detect_frame(&audio_header).is_some() {
+            header = audio_header;
+        }
+    }
+    Ok(FileType::detect(&header))
+}
+
+/// Reads up to HEADER_LEN bytes from the current position of the file.
+fn read_header(file: &mut File) -> Result<Vec<u8>, std::io::Error> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.take(HEADER_LEN as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_common_types() {
+        let cases: [(&[u8], Option<FileType>); 11] = [
+            (b"fLaC\0\0\0\x22", Some(FileType::Flac)),
+            (b"\0\0\0\x20ftypM4A ", Some(FileType::Mp4)),
+            (b"RIFF\0\0\0\0WAVEfmt ", Some(FileType::Wav)),
+            (b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR", Some(FileType::Png)),
+            (&[0xFF, 0xD8, 0xFF, 0xE0], Some(FileType::Jpeg)),
+            (&[0xFF, 0xFB, 0x90, 0x64], Some(FileType::Mp3)),
+            (&[0xFF, 0xF1, 0x50, 0x80], Some(FileType::Aac)),
+            (b"BM\x46\0\0\0\0\0\0\0\x36\0\0\0\x28\0", Some(FileType::Bmp)),
+            (b"BMW notes, 2024", None),
+            (b"BM\x10\0\0\0\0\0\0\0\x36\0\0\0\x28\0", None),
+            (b"not a media file", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(FileType::detect(header), expected);
+        }
+    }
+
+    #[test]
+    fn skips_id3_tags() {
+        let path = std::env::temp_dir().join("condenser-sniff-test.mp3");
+        let mut contents = b"ID3\x04\0\0\0\0\0\x04TAGS".to_vec();
+        contents.extend_from_slice(&[0xFF, 0xF1, 0x50, 0x80, 0, 0, 0, 0]);
+        std::fs::write(&path, contents).unwrap();
+
+        let file_type = sniff_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file_type, Some(FileType::Aac));
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::output_files::STATE_DIR_NAME;
+
+/// The name of the state database file, within the state directory.
+pub const STATE_FILE_NAME: &str = "state.json";
+
+/// The current version of the state database format.
+const STATE_VERSION: u32 = 1;
+
+/// The size, modification time and content hash of an input file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputState {
+    /// The size of the file in bytes.
+    pub size: u64,
+
+    /// The time the file was last modified.
+    pub modified: SystemTime,
+
+    /// The hex encoded SHA-256 hash of the file contents.
+    pub hash: String,
+}
+
+impl InputState {
+    /// Reads the current state of the file at path, hashing its entire contents.
+    pub fn read(path: &Path) -> Result<InputState, std::io::Error> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(InputState {
+            size: metadata.len(),
+            modified: metadata.modified()?,
+            hash: hash_file(path)?,
+        })
+    }
+
+    /// Compares this state against the current state of the file at path. The contents are
+    /// only hashed if the size or modification time differ. Returns None if the file has
+    /// changed, otherwise the current state of the file.
+    pub fn compare(&self, path: &Path) -> Result<Option<InputState>, std::io::Error> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata.modified()?;
+        if metadata.len() == self.size && modified == self.modified {
+            return Ok(Some(self.clone()));
+        }
+
+        let hash = hash_file(path)?;
+        if hash == self.hash {
+            Ok(Some(InputState {
+                size: metadata.len(),
+                modified,
+                hash,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Records how an output was produced.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputRecord {
+    /// The absolute path to the input file the output was produced from.
+    #[serde(with = "crate::serde_paths")]
+    pub input_path: PathBuf,
+
+    /// The state of the input file when the output was produced.
+    pub input: InputState,
+
+    /// The display name of the transformer that produced the output.
+    pub transformer: String,
+
+    /// The fingerprint of the transformer's configuration when the output was produced.
+    pub fingerprint: String,
+}
+
+/// The serialized form of the state database. Output paths that aren't valid Unicode
+/// can't be JSON object keys, so their records are listed separately, with their paths.
+#[derive(Deserialize)]
+struct StateFile {
+    version: u32,
+    outputs: BTreeMap<PathBuf, OutputRecord>,
+    #[serde(default)]
+    raw_outputs: Vec<RawOutput>,
+}
+
+/// The record of an output whose path isn't valid Unicode.
+#[derive(Serialize, Deserialize)]
+struct RawOutput {
+    #[serde(with = "crate::serde_paths")]
+    output_path: PathBuf,
+    record: OutputRecord,
+}
+
+/// A borrowed view of the state database in its serialized form, so that it can be saved
+/// without copying its records.
+#[derive(Serialize)]
+struct StateFileRef<'a> {
+    version: u32,
+    #[serde(serialize_with = "serialize_unicode_outputs")]
+    outputs: &'a BTreeMap<PathBuf, OutputRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    raw_outputs: Vec<RawOutputRef<'a>>,
+}
+
+/// A borrowed view of the record of an output whose path isn't valid Unicode.
+#[derive(Serialize)]
+struct RawOutputRef<'a> {
+    #[serde(with = "crate::serde_paths")]
+    output_path: &'a Path,
+    record: &'a OutputRecord,
+}
+
+/// Serializes the records of the outputs whose paths are valid Unicode, keyed by path.
+fn serialize_unicode_outputs<S: Serializer>(
+    outputs: &&BTreeMap<PathBuf, OutputRecord>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(outputs.iter().filter_map(|(path, record)| Some((path.to_str()?, record))))
+}
+
+/// Persists a record of every output produced in an output directory, so that later
+/// runs can tell precisely whether an output is up to date.
+#[derive(Clone, Debug)]
+pub struct StateDatabase {
+    /// The path to the state file.
+    path: PathBuf,
+
+    /// The output records, keyed by output path relative to the output directory.
+    outputs: BTreeMap<PathBuf, OutputRecord>,
+}
+
+impl StateDatabase {
+    /// Loads the state database for the output directory, or creates an empty one if
+    /// it doesn't exist yet.
+    pub fn load(output_dir_path: &Path) -> Result<StateDatabase, std::io::Error> {
+        let empty = StateDatabase::empty(output_dir_path);
+        let file = match File::open(&empty.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(empty),
+            Err(err) => return Err(err),
+        };
+
+        let state: StateFile = serde_json::from_reader(BufReader::new(file))?;
+        if state.version != STATE_VERSION {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported state database version {}", state.version),
+            ));
+        }
+        let mut outputs = state.outputs;
+        outputs.extend(state.raw_outputs.into_iter().map(|raw| (raw.output_path, raw.record)));
+        Ok(StateDatabase { outputs, ..empty })
+    }
+
+    /// Creates an empty state database for the output directory, ignoring any existing
+    /// state file. Saving it replaces the existing state file.
+    pub fn empty(output_dir_path: &Path) -> StateDatabase {
+        StateDatabase {
+            path: output_dir_path.join(STATE_DIR_NAME).join(STATE_FILE_NAME),
+            outputs: BTreeMap::new(),
+        }
+    }
+
+    /// Writes the state database to disk, replacing the previous state file atomically.
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let temp_path = self.path.with_extension("json.tmp");
+        let state = StateFileRef {
+            version: STATE_VERSION,
+            outputs: &self.outputs,
+            raw_outputs: self
+                .outputs
+                .iter()
+                .filter(|(path, _)| path.to_str().is_none())
+                .map(|(output_path, record)| RawOutputRef { output_path, record })
+                .collect(),
+        };
+        let result = File::create(&temp_path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &state)?;
+            writer.flush()
+        });
+        if let Err(err) = result {
+            // Don't leave a partial state file behind.
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        std::fs::rename(&temp_path, &self.path)
+    }
+
+    /// Returns the record for the output at the relative output_path, if any.
+    pub fn get(&self, output_path: &Path) -> Option<&OutputRecord> {
+        self.outputs.get(output_path)
+    }
+
+    /// Records how the output at the relative output_path was produced.
+    pub fn insert(&mut self, output_path: PathBuf, record: OutputRecord) {
+        self.outputs.insert(output_path, record);
+    }
+
+    /// Retains only the records for which predicate returns true.
+    pub fn retain<F>(&mut self, mut predicate: F)
+    where
+        F: FnMut(&Path, &OutputRecord) -> bool,
+    {
+        self.outputs.retain(|path, record| predicate(path, record));
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Returns true if there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+}
+
+/// Returns the hex encoded SHA-256 hash of the file at path.
+pub fn hash_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Returns the hex encoded SHA-256 hash of the given string.
+pub fn hash_str(str: &str) -> String {
+    to_hex(&Sha256::digest(str.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    use super::*;
+
+    fn record(input_path: PathBuf) -> OutputRecord {
+        OutputRecord {
+            input_path,
+            input: InputState {
+                size: 1,
+                modified: SystemTime::UNIX_EPOCH,
+                hash: hash_str("input"),
+            },
+            transformer: "Copy".to_string(),
+            fingerprint: hash_str("fingerprint"),
+        }
+    }
+
+    #[test]
+    fn non_unicode_paths_are_saved_and_loaded() {
+        let test_dir = std::env::temp_dir().join("condenser-state-test");
+        let _ = std::fs::remove_dir_all(&test_dir);
+
+        let invalid = PathBuf::from(OsStr::from_bytes(b"Artist/bad\xe9.flac"));
+        let mut state = StateDatabase::empty(&test_dir);
+        state.insert("Artist/good.flac".into(), record("/music/Artist/good.flac".into()));
+        state.insert(invalid.clone(), record(Path::new("/music").join(&invalid)));
+        state.save().unwrap();
+
+        let json = std::fs::read_to_string(test_dir.join(STATE_DIR_NAME).join(STATE_FILE_NAME)).unwrap();
+        assert!(json.contains(r#""Artist/good.flac":"#), "{}", json);
+        let loaded = StateDatabase::load(&test_dir).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get(&invalid), state.get(&invalid));
+        assert!(!test_dir.join(STATE_DIR_NAME).join("state.json.tmp").exists());
+
+        std::fs::remove_dir_all(&test_dir).unwrap();
+    }
+}
@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use crate::filters::{FilterAction, FilterSet};
+use crate::input_files::InputDirectory;
+use crate::transformer::{CopyTransformer, OverwriteBehavior, TransformerInstance};
+
+/// Creates a copy transformer that accepts files matching any of the globs.
+pub fn copy(name: &str, priority: u32, globs: &[&str]) -> TransformerInstance {
+    let mut transformer =
+        TransformerInstance::new(priority, OverwriteBehavior::Always, name.to_string(), Box::new(CopyTransformer));
+    for glob in globs {
+        transformer.filter.append_glob(glob::Pattern::new(glob).unwrap(), FilterAction::Accept);
+    }
+    transformer
+}
+
+/// Creates an input directory that accepts every file.
+pub fn input_dir(path: PathBuf, priority: u32) -> InputDirectory {
+    let mut filters = FilterSet::new();
+    filters.accept_unmatched = true;
+    InputDirectory { priority, filters, path }
+}
@@ -1,6 +1,10 @@
-use std::{collections::{HashMap, HashSet}, ffi::OsString, fmt::Debug, panic, path::{Path, PathBuf}};
+use std::{collections::HashMap, ffi::OsString, fmt::{Debug, Display}, panic, path::{Path, PathBuf}};
 
-use crate::filters::FilterSet;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::filters::{Candidate, FilterSet};
+use crate::plan::{Claim, ClaimSource, Conflict};
+use crate::state::{hash_str, InputState, OutputRecord};
 
 /// Indicates how the transformer should behave when the output file
 /// already exists.
@@ -13,15 +17,71 @@ pub enum OverwriteBehavior {
     Never,
 
     /// Existing files will be overwritten iff the input file is newer
-    /// than the existing file.
+    /// than the existing file, or the state database shows the file was
+    /// produced by a different transformer or configuration.
     IfNewer,
+
+    /// Existing files will be overwritten iff the state database has no record
+    /// of producing them from the same input, or the input's contents, the
+    /// transformer or its configuration have changed since.
+    IfChanged,
+}
+
+/// The reason an output is (re)produced.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TransformReason {
+    /// The overwrite behavior always overwrites outputs.
+    Always,
+
+    /// The output doesn't exist.
+    Missing,
+
+    /// The input was modified more recently than the output.
+    InputNewer,
+
+    /// The input's contents have changed since the output was produced.
+    InputChanged,
+
+    /// The output was produced from a different input.
+    InputReplaced,
+
+    /// The output was produced by a different transformer, or with a different configuration.
+    SettingsChanged,
+
+    /// The state database has no record of producing the output.
+    Unrecorded,
+}
+
+impl Display for TransformReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransformReason::Always => write!(f, "always overwritten"),
+            TransformReason::Missing => write!(f, "output missing"),
+            TransformReason::InputNewer => write!(f, "input newer"),
+            TransformReason::InputChanged => write!(f, "input changed"),
+            TransformReason::InputReplaced => write!(f, "different input"),
+            TransformReason::SettingsChanged => write!(f, "transformer settings changed"),
+            TransformReason::Unrecorded => write!(f, "no record of output"),
+        }
+    }
+}
+
+/// The outcome of checking an output against the overwrite behavior.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OverwriteCheck {
+    /// The output should be (re)produced.
+    Transform(TransformReason),
+
+    /// The output is up to date. Contains the refreshed input state if the input's
+    /// metadata changed without its contents changing.
+    Skip(Option<InputState>),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 /// An ID uniquely identifying an input.
 pub struct InputId<'a, 'b> {
-    dir_path: &'a Path,
-    file_path: &'b Path,
+    pub(crate) dir_path: &'a Path,
+    pub(crate) file_path: &'b Path,
 }
 
 impl<'a, 'b> InputId<'a, 'b> {
@@ -62,8 +122,20 @@ impl<'a, 'b> InputId<'a, 'b> {
 /// An ID uniquely identifying an output.
 pub struct OutputId(pub OsString);
 
+impl Serialize for OutputId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        crate::serde_paths::serialize_os_str(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::serde_paths::deserialize_os_string(deserializer).map(OutputId)
+    }
+}
+
 /// Transforms one file to another
-pub trait Transformer : Debug {
+pub trait Transformer : Debug + Send + Sync {
     /// Tests whether or not this transformer can handle the given input file.
     fn can_handle(&self, input: &InputId) -> bool;
 
@@ -74,8 +146,28 @@ pub trait Transformer : Debug {
     fn determine_output_path(&self, input: &InputId) -> PathBuf;
 
     /// Transforms the file at input into a new file at output. The input file is not modified, but
-    /// any file existing at output is overwritten.
-    fn transform(&self, input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error>>;
+    /// any file existing at output is overwritten. Output is typically a temporary path that is
+    /// moved into place by the caller, but it always has the same file name as the final output.
+    fn transform(&self, input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Describes the configuration of this transformer, such that any change that would
+    /// produce a different output also changes the fingerprint. Defaults to the Debug
+    /// representation.
+    fn fingerprint(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// Tests whether or not this transformer can handle every input file, without
+    /// needing to inspect it. Defaults to false.
+    fn handles_all(&self) -> bool {
+        false
+    }
+
+    /// Describes any likely mistakes in the configuration of this transformer, such as
+    /// a command that never receives the input path. Defaults to no warnings.
+    fn lint(&self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 /// Metadata about an individual instance of a transformer.
@@ -95,8 +187,9 @@ pub struct TransformerInstance {
     /// The tranformer itself.
     pub transformer: Box<dyn Transformer>,
 
-    /// Contains the files enqueued for transformation.
-    pub input_queues: HashMap<PathBuf, Vec<PathBuf>>,
+    /// The maximum number of files this transformer may process concurrently.
+    /// If None, it is only limited by the number of jobs the executor runs.
+    pub max_jobs: Option<usize>,
 }
 
 impl TransformerInstance {
@@ -113,27 +206,31 @@ impl TransformerInstance {
             name,
             transformer,
             filter: FilterSet::new(),
-            input_queues: HashMap::new(),
+            max_jobs: None,
         }
     }
 
-    /// Finds acceptable input files within unprocessed_files, adds them to the input
-    /// queue associated with input_dir_path and adds the output ids to claimed_outputs.
+    /// Finds acceptable input files among the candidates in unprocessed_files, removes
+    /// them and appends a claim for each to claims, adding the output ids to
+    /// claimed_outputs. Files whose output has already been claimed are recorded in
+    /// blocked, unless a conflict has already been recorded for them.
     pub fn claim_outputs(
-        &mut self,
+        &self,
         input_dir_path: &Path,
-        unprocessed_files: &mut Vec<PathBuf>,
-        claimed_outputs: &mut HashSet<OutputId>,
-        output_paths: &mut HashSet<PathBuf>,
+        unprocessed_files: &mut Vec<Candidate>,
+        claimed_outputs: &mut HashMap<OutputId, ClaimSource>,
+        claims: &mut Vec<Claim>,
+        blocked: &mut HashMap<PathBuf, Conflict>,
     ) -> u64 {
         let mut claim_count = 0;
-        let transformer = &mut self.transformer;
-        unprocessed_files.retain(|path| {
-            if !self.filter.is_acceptable(path) {
+        let transformer = &self.transformer;
+        unprocessed_files.retain(|candidate| {
+            if !self.filter.accepts(candidate) {
                 // Skip this file since it doesn't pass the filter.
                 return true;
             }
 
+            let path = candidate.path();
             let input_id = InputId {
                 dir_path: input_dir_path,
                 file_path: path,
@@ -144,92 +241,160 @@ impl TransformerInstance {
             }
 
             let output_id = transformer.determine_output_id(&input_id);
-            if !claimed_outputs.insert(output_id) {
+            let source = ClaimSource {
+                input_dir: input_dir_path.to_path_buf(),
+                file_path: path.to_path_buf(),
+                transformer: self.name.clone(),
+            };
+            if let Some(winner) = claimed_outputs.get(&output_id) {
                 // Skip this file since a previous transformer has claimed the output.
+                blocked.entry(path.to_path_buf()).or_insert_with(|| Conflict {
+                    output_id,
+                    winner: winner.clone(),
+                    loser: source,
+                });
                 return true;
             }
-
-            // Log this as an output path.
-            output_paths.insert(transformer.determine_output_path(&input_id));
-
-            // Now we have successully claimed this file, so add it to our queue.
-            self.input_queues
-                .entry(input_dir_path.to_path_buf())
-                .or_insert_with(Vec::new)
-                .push(path.to_path_buf());
+            claimed_outputs.insert(output_id.clone(), source);
+
+            // Now we have successully claimed this file, so record the claim.
+            claims.push(Claim {
+                input_dir: input_dir_path.to_path_buf(),
+                file_path: path.to_path_buf(),
+                output_id,
+                output_path: transformer.determine_output_path(&input_id),
+            });
             claim_count += 1;
             false
         });
         claim_count
     }
 
-    /// Processes all input queues, outputting to output_dir.
-    pub fn process_queues(
-        &mut self,
+    /// Returns a hash of the transformer's fingerprint, identifying its configuration.
+    pub fn fingerprint(&self) -> String {
+        hash_str(&self.transformer.fingerprint())
+    }
+
+    /// Transforms the input of a claim, outputting to output_dir. The output is first
+    /// written to temp_path, and only moved into place if the transformation succeeds.
+    /// record is the state database's record of the existing output, if any. Returns
+    /// the new record for the output, or None if the existing record is unchanged.
+    pub fn process_claim(
+        &self,
+        claim: &Claim,
         output_dir: &Path,
-    ) -> Vec<(PathBuf, Box<dyn std::error::Error>)> {
-        let mut failed = Vec::new();
-
-        // We swap these around to avoid borrowing issues when iterating through
-        // the queues. We could also do more fine grained borrows in the loop,
-        // but this is simpler.
-        let mut input_queues = HashMap::new();
-        core::mem::swap(&mut input_queues, &mut self.input_queues);
-
-        for (parent_dir, file_paths) in &mut input_queues {
-            for file_path in file_paths.drain(..) {
-                let input_path: PathBuf = [parent_dir, &file_path].iter().collect();
-                // TODO: Use a temporary file
-                // TODO: Add handling of existing files
-                let output_path: PathBuf = [
-                    output_dir,
-                    &self
-                        .transformer
-                        .determine_output_path(&InputId::new(&parent_dir, &file_path)),
-                ]
-                .iter()
-                .collect();
-
-                if let Err(err) = self.transform(&input_path, &output_path) {
-                    failed.push((input_path, err));
+        temp_path: &Path,
+        record: Option<&OutputRecord>,
+    ) -> Result<Option<OutputRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let input_path = claim.input_path();
+        let output_path = output_dir.join(&claim.output_path);
+        self.transform(&input_path, &output_path, temp_path, record)
+    }
+
+    /// Checks the output at output_path against the overwrite behavior, returning why
+    /// it should be transformed, or that it should be skipped. record is the state
+    /// database's record of the existing output, if any. Neither file is modified.
+    pub fn check_overwrite(
+        &self,
+        input_path: &Path,
+        output_path: &Path,
+        record: Option<&OutputRecord>,
+    ) -> Result<OverwriteCheck, Box<dyn std::error::Error + Send + Sync>> {
+        use OverwriteCheck::*;
+
+        match &self.overwrite_behavior {
+            OverwriteBehavior::Always => Ok(Transform(TransformReason::Always)),
+            _ if !Path::exists(output_path) => Ok(Transform(TransformReason::Missing)),
+            OverwriteBehavior::Never => Ok(Skip(None)),
+            OverwriteBehavior::IfNewer => {
+                // Outputs without a record predate the state database, so they
+                // can only be judged by their modification time.
+                if let Some(record) = record {
+                    if !self.is_same_configuration(record) {
+                        return Ok(Transform(TransformReason::SettingsChanged));
+                    }
+                }
+
+                let output_meta = std::fs::metadata(&output_path)?;
+                let input_meta = std::fs::metadata(&input_path)?;
+
+                // TODO: We might want to handle this error differently, since
+                // it indicates a platform limitation, not a potentially transient
+                // IO error
+                match input_meta.modified()? > output_meta.modified()? {
+                    true => Ok(Transform(TransformReason::InputNewer)),
+                    false => Ok(Skip(None)),
+                }
+            }
+            OverwriteBehavior::IfChanged => {
+                let record = match record {
+                    Some(record) => record,
+                    None => return Ok(Transform(TransformReason::Unrecorded)),
+                };
+                if record.input_path != input_path {
+                    return Ok(Transform(TransformReason::InputReplaced));
+                }
+                if !self.is_same_configuration(record) {
+                    return Ok(Transform(TransformReason::SettingsChanged));
+                }
+
+                match record.input.compare(input_path)? {
+                    Some(state) if state == record.input => Ok(Skip(None)),
+                    Some(state) => Ok(Skip(Some(state))),
+                    None => Ok(Transform(TransformReason::InputChanged)),
                 }
             }
         }
-        failed
     }
 
-    /// Runs a transforms the file at input_path to output_path,
+    /// Tests whether or not record was produced by this transformer with its current
+    /// configuration.
+    fn is_same_configuration(&self, record: &OutputRecord) -> bool {
+        record.transformer == self.name && record.fingerprint == self.fingerprint()
+    }
+
+    /// Runs a transforms the file at input_path to output_path via temp_path,
     /// using the specified overrwrite behavior
     fn transform(
         &self,
         input_path: &Path,
         output_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        temp_path: &Path,
+        record: Option<&OutputRecord>,
+    ) -> Result<Option<OutputRecord>, Box<dyn std::error::Error + Send + Sync>> {
         assert!(input_path.is_absolute());
         assert!(output_path.is_absolute());
-        match &self.overwrite_behavior {
-            OverwriteBehavior::Always => (),
-            OverwriteBehavior::Never => {
-                if Path::exists(&output_path) {
-                    return Ok(());
-                }
-            }
-            OverwriteBehavior::IfNewer => {
-                if Path::exists(&output_path) {
-                    let output_meta = std::fs::metadata(&output_path)?;
-                    let input_meta = std::fs::metadata(&input_path)?;
-
-                    // TODO: We might want to handle this error differently, since
-                    // it indicates a platform limitation, not a potentially transient
-                    // IO error
-                    if input_meta.modified()? <= output_meta.modified()? {
-                        return Ok(());
-                    }
-                }
+        match self.check_overwrite(input_path, output_path, record)? {
+            OverwriteCheck::Transform(_) => (),
+            OverwriteCheck::Skip(None) => return Ok(None),
+            OverwriteCheck::Skip(Some(input)) => {
+                // Unchanged, but the metadata needs updating to avoid rehashing next time.
+                return Ok(record.map(|record| OutputRecord {
+                    input,
+                    ..record.clone()
+                }));
             }
         }
 
-        self.transformer.transform(&input_path, &output_path)
+        // Read the input state first, so that changes made during the transformation
+        // are picked up by the next run.
+        let input = InputState::read(input_path)?;
+        if let Err(err) = self.transformer.transform(input_path, temp_path) {
+            // Don't leave a partial output behind - there may be nothing to remove.
+            let _ = std::fs::remove_file(temp_path);
+            return Err(err);
+        }
+
+        if let Some(output_parent) = output_path.parent() {
+            std::fs::create_dir_all(output_parent)?;
+        }
+        std::fs::rename(temp_path, output_path)?;
+        Ok(Some(OutputRecord {
+            input_path: input_path.to_path_buf(),
+            input,
+            transformer: self.name.clone(),
+            fingerprint: self.fingerprint(),
+        }))
     }
 }
 
@@ -242,6 +407,10 @@ impl Transformer for CopyTransformer {
         true
     }
 
+    fn handles_all(&self) -> bool {
+        true
+    }
+
     fn determine_output_id(&self, input: &InputId) -> OutputId {
         OutputId(input.file_path.with_extension("").into_os_string())
     }
@@ -250,7 +419,7 @@ impl Transformer for CopyTransformer {
         input.file_path.to_path_buf()
     }
 
-    fn transform(&self, input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    fn transform(&self, input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // Create the parent directory if it doesn't exist
         if let Some(output_parent) = output.parent() {
             std::fs::create_dir_all(output_parent).map_err(Box::new)?;
@@ -1,35 +1,219 @@
+mod error;
+mod expand;
+mod layers;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
-use condenser::{FilterAction, FilterPattern, FilterSet, InputDirectory};
+use condenser::{FilterAction, FilterPattern, FilterSet, GlobOptions, InputDirectory, OrphanPolicy, TransformerInstance};
+use serde::de::IgnoredAny;
 use serde::Deserialize;
+use toml::Spanned;
+
+pub use error::*;
+pub use expand::*;
+pub use layers::*;
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct Config {
-    pub output_dir: String,
+    pub output_dir: Spanned<String>,
     pub input_dirs: Vec<CfgInputDirectory>,
     pub transformers: Vec<CfgTransformerInstance>,
+    #[serde(default)]
+    pub orphans: CfgOrphanPolicy,
+    #[serde(default)]
+    pub filter_sets: BTreeMap<String, CfgNamedFilterSet>,
+    #[serde(default)]
+    pub commands: BTreeMap<String, CfgNamedCommand>,
+}
+
+/// A filter set that can be referred to by name from any list of filters.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct CfgNamedFilterSet {
+    pub filters: Vec<Spanned<CfgFilter>>,
+    #[serde(default)]
+    pub accept_unmatched: bool,
+}
+
+/// A command that can be referred to by name from any command. If it has params, every
+/// parameter used in the program or args must have a default value in them.
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct CfgNamedCommand {
+    pub program: Spanned<String>,
+    pub args: Vec<String>,
+    pub success_codes: Option<Vec<i32>>,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct CfgInputDirectory {
     pub priority: u32,
-    pub path: String,
-    pub filters: Vec<CfgFilter>,
+    pub path: Spanned<String>,
+    pub filters: Vec<Spanned<CfgFilter>>,
+    #[serde(default)]
+    pub accept_unmatched: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+pub struct CfgOrphanPolicy {
+    #[serde(default)]
+    pub protect: Vec<Spanned<CfgFilter>>,
+    pub trash_dir: Option<Spanned<String>>,
+    pub max_deletions: Option<usize>,
+    pub max_deletion_percent: Option<f64>,
+}
+
+/// A pattern and the action to take on files it matches. A filter is written as a table
+/// with exactly one pattern key and an optional action, e.g. `{ Glob = "*.log", action =
+/// "Reject" }`. Any other key is rejected rather than silently ignored.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(try_from = "RawCfgFilter")]
+pub struct CfgFilter {
+    pub pattern: CfgFilterPattern,
+    pub action: CfgFilterAction,
+}
+
+/// A filter as written, with any keys besides its pattern and action.
+#[derive(Deserialize)]
+struct RawCfgFilter {
+    #[serde(flatten)]
+    pattern: CfgFilterPattern,
+    #[serde(default)]
+    action: CfgFilterAction,
+    #[serde(flatten)]
+    rest: BTreeMap<String, IgnoredAny>,
+}
+
+/// The keys that name a filter pattern.
+const PATTERN_KEYS: &[&str] = &[
+    "Glob", "Regex", "All", "Any", "Not", "Set", "Size", "Modified", "Age", "Content", "Named",
+];
+
+impl TryFrom<RawCfgFilter> for CfgFilter {
+    type Error = String;
+
+    fn try_from(raw: RawCfgFilter) -> Result<Self, Self::Error> {
+        match raw.rest.keys().next() {
+            None => Ok(CfgFilter {
+                pattern: raw.pattern,
+                action: raw.action,
+            }),
+            Some(key) if PATTERN_KEYS.contains(&key.as_str()) => {
+                Err(format!("a filter has a single pattern, found `{}` as well", key))
+            }
+            Some(key) => Err(format!(
+                "unknown field `{}`, expected `action` or one of `{}`",
+                key,
+                PATTERN_KEYS.join("`, `")
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub enum CfgFilterPattern {
+    Glob(CfgGlob),
+    Regex(CfgRegex),
+    All(Vec<CfgFilterPattern>),
+    Any(Vec<CfgFilterPattern>),
+    Not(Box<CfgFilterPattern>),
+    Set(CfgFilterSet),
+    Size {
+        min: Option<String>,
+        max: Option<String>,
+    },
+    Modified {
+        after: Option<String>,
+        before: Option<String>,
+    },
+    Age {
+        min: Option<String>,
+        max: Option<String>,
+    },
+    Content(String),
+    /// Refers to a filter set defined in the filter_sets table.
+    Named(String),
+}
+
+impl CfgFilterPattern {
+    /// Returns the names of the filter sets referred to by this pattern.
+    fn references(&self) -> Vec<&str> {
+        match self {
+            CfgFilterPattern::Named(name) => vec![name.as_str()],
+            CfgFilterPattern::All(patterns) | CfgFilterPattern::Any(patterns) => {
+                patterns.iter().flat_map(|pattern| pattern.references()).collect()
+            }
+            CfgFilterPattern::Not(pattern) => pattern.references(),
+            CfgFilterPattern::Set(set) => set.filters.iter().flat_map(|filter| filter.pattern.references()).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum CfgGlob {
+    Pattern(String),
+    Options(CfgGlobOptions),
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CfgGlobOptions {
+    pub pattern: String,
+    #[serde(default = "default_true")]
+    pub case_sensitive: bool,
+    #[serde(default)]
+    pub literal_separator: bool,
+    #[serde(default)]
+    pub literal_leading_dot: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum CfgRegex {
+    Pattern(String),
+    Options(CfgRegexOptions),
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CfgRegexOptions {
+    pub pattern: String,
+    #[serde(default = "default_true")]
+    pub case_sensitive: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
-pub enum CfgFilter {
-    Glob(String),
-    Regex(String),
+pub struct CfgFilterSet {
+    pub filters: Vec<CfgFilter>,
+    #[serde(default)]
+    pub accept_unmatched: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+pub enum CfgFilterAction {
+    #[default]
+    Accept,
+    Reject,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct CfgTransformerInstance {
-    pub name: String,
+    pub name: Spanned<String>,
     pub priority: u32,
     pub overwrite: CfgOverwriteBehavior,
-    pub filters: Vec<CfgFilter>,
+    pub filters: Vec<Spanned<CfgFilter>>,
+    #[serde(default)]
+    pub accept_unmatched: bool,
     pub transformer: CfgTranformerSelection,
+    pub max_jobs: Option<usize>,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
@@ -37,113 +221,270 @@ pub enum CfgOverwriteBehavior {
     Always,
     Never,
     IfNewer,
+    IfChanged,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub enum CfgTranformerSelection {
     CopyTransformer,
-    CommandTransformer(CfgCommandTransformer)
+    CommandTransformer(Box<CfgCommandTransformer>)
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct CfgCommandTransformer {
-    pub transform_command: CfgCommand,
-    pub check_command: Option<CfgCommand>,
+    pub transform_command: Spanned<CfgCommand>,
+    pub check_command: Option<Spanned<CfgCommand>>,
     pub output_file_ext: Option<String>,
 }
 
-#[derive(Deserialize, Debug, PartialEq)]
+/// A command, given either as a program and args, or as the name of a command defined in
+/// the commands table. params overrides the parameters of a named command, or provides
+/// the parameters of a program and args.
+#[derive(Deserialize, Debug, PartialEq, Default)]
 pub struct CfgCommand {
-    pub program: String,
+    pub command: Option<String>,
+    pub program: Option<String>,
+    #[serde(default)]
     pub args: Vec<String>,
+    pub success_codes: Option<Vec<i32>>,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
 }
 
-impl TryFrom<CfgFilter> for condenser::FilterPattern {
-    // TODO: Proper error type for this
-    type Error = Box<dyn std::error::Error>;
-    
-    fn try_from(f: CfgFilter) -> Result<Self, Self::Error> {
-        match f {
-            CfgFilter::Glob(str) => {
-                let glob = match glob::Pattern::from_str(&str) {
-                    Ok(glob) => Ok(glob),
-                    // PatternError doesn't implement try, so 
-                    Err(err) => Err(Box::new(err)),
-                }?;
-                Ok(FilterPattern::Glob(glob))
-            },
-            CfgFilter::Regex(str) => {
-                Ok(FilterPattern::Regex(regex::Regex::from_str(&str)?))
+impl From<&CfgNamedCommand> for CfgCommand {
+    fn from(cfg: &CfgNamedCommand) -> Self {
+        CfgCommand {
+            command: None,
+            program: Some(cfg.program.get_ref().clone()),
+            args: cfg.args.clone(),
+            success_codes: cfg.success_codes.clone(),
+            params: cfg.params.clone(),
+        }
+    }
+}
+
+/// The filter sets defined in the filter_sets table, as they're converted.
+#[derive(Default)]
+struct NamedFilterSets {
+    /// The filter sets converted so far, by name.
+    sets: HashMap<String, FilterSet>,
+
+    /// The names of the filter sets currently being converted, innermost last.
+    converting: Vec<String>,
+}
+
+impl NamedFilterSets {
+    /// Returns a copy of the named filter set.
+    fn get(&self, name: &str) -> Result<FilterSet, String> {
+        match self.sets.get(name) {
+            Some(set) => Ok(set.clone()),
+            None if self.converting.iter().any(|converting| converting == name) => {
+                Err(format!("Filter set '{}' refers back to itself", name))
             }
+            None => Err(format!("Unknown filter set '{}'", name)),
         }
     }
 }
 
-impl TryFrom<CfgInputDirectory> for condenser::InputDirectory {
-    // TODO: Proper error type for this
-    type Error = Box<dyn std::error::Error>;
- 
-    fn try_from(dir: CfgInputDirectory) -> Result<Self, Self::Error> {
-        let mut filters = FilterSet::new();
-        for filter in dir.filters {
-            let filter = filter.try_into()?;
-            filters.append(filter, FilterAction::Accept);
+/// Converts a configured pattern, looking up any filter sets it refers to in named.
+fn filter_pattern(cfg: CfgFilterPattern, named: &NamedFilterSets) -> Result<FilterPattern, String> {
+    match cfg {
+        CfgFilterPattern::Glob(cfg) => {
+            let (str, options) = match cfg {
+                CfgGlob::Pattern(str) => (str, GlobOptions::default()),
+                CfgGlob::Options(cfg) => {
+                    let options = GlobOptions {
+                        case_sensitive: cfg.case_sensitive,
+                        literal_separator: cfg.literal_separator,
+                        literal_leading_dot: cfg.literal_leading_dot,
+                    };
+                    (cfg.pattern, options)
+                }
+            };
+            let glob = glob::Pattern::from_str(&str).map_err(|err| format!("Invalid glob '{}': {}", str, err))?;
+            Ok(FilterPattern::Glob(glob, options))
+        },
+        CfgFilterPattern::Regex(cfg) => {
+            let str = regex_source(cfg);
+            let regex = regex::Regex::from_str(&str).map_err(|err| format!("Invalid regex '{}': {}", str, err))?;
+            Ok(FilterPattern::Regex(regex))
         }
-        Ok(InputDirectory {
-            priority: dir.priority,
-            path: dir.path.into(),
-            filters
-        })
+        CfgFilterPattern::All(patterns) => Ok(FilterPattern::All(filter_patterns(patterns, named)?)),
+        CfgFilterPattern::Any(patterns) => Ok(FilterPattern::Any(filter_patterns(patterns, named)?)),
+        CfgFilterPattern::Not(pattern) => Ok(FilterPattern::Not(Box::new(filter_pattern(*pattern, named)?))),
+        CfgFilterPattern::Set(set) => Ok(FilterPattern::Set(filter_set(set.filters, set.accept_unmatched, named)?)),
+        CfgFilterPattern::Size { min, max } => Ok(FilterPattern::Size {
+            min: min.as_deref().map(parse_size).transpose()?,
+            max: max.as_deref().map(parse_size).transpose()?,
+        }),
+        CfgFilterPattern::Modified { after, before } => Ok(FilterPattern::Modified {
+            after: after.as_deref().map(parse_time).transpose()?,
+            before: before.as_deref().map(parse_time).transpose()?,
+        }),
+        CfgFilterPattern::Age { min, max } => Ok(FilterPattern::Age {
+            min: min.as_deref().map(parse_duration).transpose()?,
+            max: max.as_deref().map(parse_duration).transpose()?,
+        }),
+        CfgFilterPattern::Content(str) => Ok(FilterPattern::Content(str.parse()?)),
+        CfgFilterPattern::Named(name) => Ok(FilterPattern::Set(named.get(&name)?)),
     }
 }
 
-impl TryFrom<CfgTransformerInstance> for condenser::TransformerInstance {
-    // TODO: Proper error type for this
-    type Error = Box<dyn std::error::Error>;
+/// Returns the pattern of a configured regex, with its flags given inline.
+fn regex_source(cfg: CfgRegex) -> String {
+    match cfg {
+        CfgRegex::Pattern(str) => str,
+        CfgRegex::Options(CfgRegexOptions { pattern, case_sensitive: true }) => pattern,
+        CfgRegex::Options(CfgRegexOptions { pattern, case_sensitive: false }) => format!("(?i){}", pattern),
+    }
+}
 
-    fn try_from(cfg: CfgTransformerInstance) -> Result<Self, Self::Error> {
-        use condenser::*;
+impl From<CfgFilterAction> for condenser::FilterAction {
+    fn from(cfg: CfgFilterAction) -> Self {
+        match cfg {
+            CfgFilterAction::Accept => FilterAction::Accept,
+            CfgFilterAction::Reject => FilterAction::Reject,
+        }
+    }
+}
 
-        let transformer: Box<dyn Transformer> = match cfg.transformer {
-            CfgTranformerSelection::CopyTransformer => Box::new(CopyTransformer),
-            CfgTranformerSelection::CommandTransformer(cfg) => {
-                let transform_command = cfg.transform_command.try_into().expect("Invalid command definition");
-                let check_command = cfg.check_command.map(|cmd| cmd.try_into().expect("Invalid command definition"));
-                let transfomer = transformers::CommandTransformer {
-                    transform_command,
-                    check_command,
-                    output_file_extension: cfg.output_file_ext.map(|s| s.into())
-                };
-                Box::new(transfomer)
-            }
-        };
+/// Parses a size in bytes, such as "2GB" or "500 MiB". Decimal units (KB, MB, GB, TB) are
+/// powers of 1000 and binary units (KiB, MiB, GiB, TiB) are powers of 1024.
+fn parse_size(str: &str) -> Result<u64, String> {
+    let str = str.trim();
+    let split = str.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(str.len());
+    let (number, unit) = str.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1000,
+        "mb" => 1000_u64.pow(2),
+        "gb" => 1000_u64.pow(3),
+        "tb" => 1000_u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("Unknown size unit '{}' in '{}'", unit.trim(), str)),
+    };
+    let number: f64 = number.parse().map_err(|_| format!("Invalid size '{}'", str))?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a UTC date, such as "2024-01-31", or a date and time, such as "2024-01-31 18:00:00".
+fn parse_time(str: &str) -> Result<std::time::SystemTime, String> {
+    let str = str.trim();
+    let time = match str.len() {
+        10 => humantime::parse_rfc3339_weak(&format!("{} 00:00:00", str)),
+        _ => humantime::parse_rfc3339_weak(str),
+    };
+    time.map_err(|err| format!("Invalid time '{}': {}", str, err))
+}
+
+/// Parses a duration, such as "90days" or "1h 30m".
+fn parse_duration(str: &str) -> Result<std::time::Duration, String> {
+    humantime::parse_duration(str).map_err(|err| format!("Invalid duration '{}': {}", str, err))
+}
+
+/// Converts each of the configured patterns.
+fn filter_patterns(patterns: Vec<CfgFilterPattern>, named: &NamedFilterSets) -> Result<Vec<FilterPattern>, String> {
+    patterns.into_iter().map(|pattern| filter_pattern(pattern, named)).collect()
+}
 
-        let mut inst = TransformerInstance::new(cfg.priority, cfg.overwrite.into(), cfg.name, transformer);
-        for filter in cfg.filters {
-            let filter = filter.try_into()?;
-            inst.filter.append(filter, FilterAction::Accept);
+/// Builds a filter set from the configured filters, in order.
+fn filter_set(filters: Vec<CfgFilter>, accept_unmatched: bool, named: &NamedFilterSets) -> Result<FilterSet, String> {
+    let mut set = FilterSet::new();
+    set.accept_unmatched = accept_unmatched;
+    for filter in filters {
+        append_filter(&mut set, filter, named)?;
+    }
+    Ok(set)
+}
+
+/// Converts the configured filter and appends it to set. Regexes are appended by their
+/// pattern, with flags given inline, so that the set can compile them.
+fn append_filter(set: &mut FilterSet, filter: CfgFilter, named: &NamedFilterSets) -> Result<(), String> {
+    match filter.pattern {
+        CfgFilterPattern::Regex(cfg) => {
+            let str = regex_source(cfg);
+            set.append_regex_str(&str, filter.action.into())
+                .map_err(|err| format!("Invalid regex '{}': {}", str, err))
+        }
+        pattern => {
+            set.append(filter_pattern(pattern, named)?, filter.action.into());
+            Ok(())
         }
+    }
+}
+
+/// The arguments substituted with the input and output paths, which can't be used as parameters.
+const PATH_ARGUMENTS: [&str; 2] = ["!INPUTPATH!", "!OUTPUTPATH!"];
 
-        Ok(inst)
+/// Substitutes each `!NAME!` parameter in str with its value, failing if any parameter
+/// doesn't have a value.
+fn substitute_params(str: &str, params: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = str;
+    while let Some(start) = rest.find('!') {
+        let name = &rest[start + 1..];
+        let end = match name.find('!') {
+            Some(end) if !name[..end].is_empty() && name[..end].chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => end,
+            _ => {
+                // Not a parameter, so the '!' is kept as is.
+                result.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+        let name = &name[..end];
+        let placeholder = &rest[start..start + end + 2];
+        result.push_str(&rest[..start]);
+        match params.get(name) {
+            Some(value) => result.push_str(value),
+            None if PATH_ARGUMENTS.contains(&placeholder) => result.push_str(placeholder),
+            None => return Err(format!("No value for parameter '{}' in '{}'", name, str)),
+        }
+        rest = &rest[start + end + 2..];
     }
+    result.push_str(rest);
+    Ok(result)
 }
 
 impl TryFrom<CfgCommand> for transformers::FullCommand {
-    // TODO: Proper error type for this
-    type Error = Box<dyn std::error::Error>;
+    /// The reason the command is invalid.
+    type Error = String;
 
+    /// Converts a command given as a program and args, substituting its parameters. Commands
+    /// without parameters are taken as is, so that their args may contain `!NAME!` literally.
     fn try_from(cfg: CfgCommand) -> Result<Self, Self::Error> {
         use transformers::{CommandArgument, FullCommand};
 
-        let mut cmd = FullCommand::new(cfg.program.into());
+        let substitute = |str: &str| match cfg.params.is_empty() {
+            true => Ok(str.to_string()),
+            false => substitute_params(str, &cfg.params),
+        };
+
+        if let Some(name) = cfg.params.keys().find(|name| PATH_ARGUMENTS.contains(&format!("!{}!", name).as_str())) {
+            return Err(format!("'{}' is reserved, and can't be used as a parameter", name));
+        }
+        let program = match cfg.program {
+            Some(program) => expand(&substitute(&program)?)?,
+            None => return Err("either program or command must be given".into()),
+        };
+        if program.is_empty() {
+            return Err("program may not be empty".into());
+        }
+        let mut cmd = FullCommand::new(program.into());
         for arg in cfg.args {
             let arg = match arg.as_str() {
                 "!INPUTPATH!" => CommandArgument::InputPath,
                 "!OUTPUTPATH!" => CommandArgument::OutputPath,
-                str => CommandArgument::Arg(str.into())
+                str => CommandArgument::Arg(expand(&substitute(str)?)?.into())
             };
             cmd.args.push(arg);
         }
+        if let Some(success_codes) = cfg.success_codes {
+            cmd.success_codes = success_codes;
+        }
 
         Ok(cmd)
     }
@@ -156,6 +497,513 @@ impl From<CfgOverwriteBehavior> for condenser::OverwriteBehavior {
             CfgOverwriteBehavior::Always => Always,
             CfgOverwriteBehavior::Never => Never,
             CfgOverwriteBehavior::IfNewer => IfNewer,
+            CfgOverwriteBehavior::IfChanged => IfChanged,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Everything described by the configuration file.
+pub struct Library {
+    pub transformers: Vec<TransformerInstance>,
+    pub input_dirs: Vec<InputDirectory>,
+    pub output_dir: PathBuf,
+    pub orphan_policy: OrphanPolicy,
+}
+
+impl ResolvedConfig {
+    /// Parses and converts the configuration, returning every error found.
+    pub fn load(&self) -> Result<Library, Vec<ConfigError>> {
+        let config: Config =
+            toml::from_str(&self.text).map_err(|err| vec![ConfigError::parse(self.path.as_deref(), err)])?;
+        config.into_library(&self.text)
+    }
+}
+
+impl Config {
+    /// Converts the configuration into a library, returning every error found rather than
+    /// just the first. source is the text the configuration was parsed from, and is used to
+    /// locate errors.
+    pub fn into_library(self, source: &str) -> Result<Library, Vec<ConfigError>> {
+        let mut converter = Converter {
+            source,
+            errors: Vec::new(),
+            filter_sets: NamedFilterSets::default(),
+            commands: HashMap::new(),
+        };
+
+        // Named definitions are converted first, so that they can be referred to.
+        let mut filter_sets = self.filter_sets;
+        while let Some(name) = filter_sets.keys().next().cloned() {
+            converter.define_filter_set(name, &mut filter_sets);
+        }
+        for (name, command) in self.commands {
+            converter.define_command(name, command);
+        }
+
+        let output_dir = converter.path(&Section::OutputDirectory, &self.output_dir);
+        let input_dirs: Vec<InputDirectory> = self
+            .input_dirs
+            .into_iter()
+            .enumerate()
+            .map(|(index, dir)| converter.input_dir(index, dir, &output_dir))
+            .collect();
+        let transformers: Vec<TransformerInstance> = self
+            .transformers
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, transformer)| converter.transformer(index, transformer))
+            .collect();
+        let orphan_policy = converter.orphan_policy(self.orphans, &output_dir, &input_dirs);
+
+        if !converter.errors.is_empty() {
+            converter.errors.sort_by_key(|err| err.location());
+            return Err(converter.errors);
+        }
+        Ok(Library {
+            transformers,
+            input_dirs,
+            output_dir,
+            orphan_policy,
+        })
+    }
+}
+
+/// Converts the sections of a configuration, collecting every error found along the way.
+struct Converter<'a> {
+    /// The text the configuration was parsed from.
+    source: &'a str,
+
+    /// The errors found so far.
+    errors: Vec<ConfigError>,
+
+    /// The named filter sets.
+    filter_sets: NamedFilterSets,
+
+    /// The named commands, or None for those that are invalid.
+    commands: HashMap<String, Option<CfgNamedCommand>>,
+}
+
+impl<'a> Converter<'a> {
+    /// Returns the location of the byte offset within the configuration file.
+    fn location(&self, offset: usize) -> Location {
+        Location::from_offset(self.source, offset)
+    }
+
+    /// Expands the environment variables and home directory in a path, recording an error
+    /// and returning the path as written if it can't be expanded.
+    fn path(&mut self, section: &Section, cfg: &Spanned<String>) -> PathBuf {
+        match expand(cfg.get_ref()) {
+            Ok(path) => path.into(),
+            Err(reason) => {
+                self.errors.push(ConfigError::InvalidPath {
+                    section: section.clone(),
+                    path: cfg.get_ref().clone(),
+                    reason,
+                    location: self.location(cfg.start()),
+                });
+                cfg.get_ref().into()
+            }
+        }
+    }
+
+    /// Builds a filter set from the configured filters, recording an error for each
+    /// invalid filter.
+    fn filter_set(&mut self, section: &Section, filters: Vec<Spanned<CfgFilter>>, accept_unmatched: bool) -> FilterSet {
+        let mut set = FilterSet::new();
+        set.accept_unmatched = accept_unmatched;
+        for filter in filters {
+            let (start, end) = filter.span();
+            if let Err(reason) = append_filter(&mut set, filter.into_inner(), &self.filter_sets) {
+                self.errors.push(ConfigError::InvalidFilter {
+                    section: section.clone(),
+                    filter: self.source.get(start..end).unwrap_or_default().to_string(),
+                    reason,
+                    location: self.location(start),
+                });
+            }
+        }
+        set
+    }
+
+    /// Converts the named filter set, after the filter sets it refers to. The definition
+    /// is removed from definitions, so that each set is only converted once.
+    fn define_filter_set(&mut self, name: String, definitions: &mut BTreeMap<String, CfgNamedFilterSet>) {
+        let cfg = match definitions.remove(&name) {
+            Some(cfg) => cfg,
+            None => return,
+        };
+        self.filter_sets.converting.push(name.clone());
+        let references: Vec<String> = cfg
+            .filters
+            .iter()
+            .flat_map(|filter| filter.get_ref().pattern.references())
+            .map(|reference| reference.to_string())
+            .collect();
+        for reference in references {
+            self.define_filter_set(reference, definitions);
+        }
+
+        let section = Section::FilterSet { name: name.clone() };
+        let set = self.filter_set(&section, cfg.filters, cfg.accept_unmatched);
+        self.filter_sets.converting.pop();
+        self.filter_sets.sets.insert(name, set);
+    }
+
+    /// Checks the named command with its default parameters, so that any error is reported
+    /// once, where it's defined.
+    fn define_command(&mut self, name: String, cfg: CfgNamedCommand) {
+        let section = Section::Command { name: name.clone() };
+        let command = CfgCommand::from(&cfg).try_into();
+        let valid = self.record(&section, cfg.program.start(), command).is_some();
+        self.commands.insert(name, valid.then_some(cfg));
+    }
+
+    /// Converts a command, recording an error and returning None if it's invalid. A named
+    /// command is looked up, and its parameters overridden.
+    fn command(&mut self, section: &Section, cfg: Spanned<CfgCommand>) -> Option<transformers::FullCommand> {
+        let start = cfg.start();
+        let cfg = cfg.into_inner();
+        let name = match &cfg.command {
+            Some(name) => name,
+            None => return self.record(section, start, cfg.try_into()),
+        };
+        let definition = match self.commands.get(name) {
+            Some(Some(definition)) => definition,
+            // The error has already been recorded where the command is defined.
+            Some(None) => return None,
+            None => return self.record(section, start, Err(format!("Unknown command '{}'", name))),
+        };
+        if cfg.program.is_some() || !cfg.args.is_empty() {
+            let reason = format!("program and args can't be given along with command '{}'", name);
+            return self.record(section, start, Err(reason));
+        }
+        if let Some(param) = cfg.params.keys().find(|param| !definition.params.contains_key(*param)) {
+            let reason = format!("Unknown parameter '{}' for command '{}'", param, name);
+            return self.record(section, start, Err(reason));
+        }
+
+        let mut command = CfgCommand::from(definition);
+        command.params.extend(cfg.params);
+        command.success_codes = cfg.success_codes.or(command.success_codes);
+        self.record(section, start, command.try_into())
+    }
+
+    /// Returns the converted command, or records an error at the byte offset start and
+    /// returns None if it's invalid.
+    fn record(
+        &mut self,
+        section: &Section,
+        start: usize,
+        result: Result<transformers::FullCommand, String>,
+    ) -> Option<transformers::FullCommand> {
+        match result {
+            Ok(command) => Some(command),
+            Err(reason) => {
+                self.errors.push(ConfigError::InvalidCommand {
+                    section: section.clone(),
+                    reason,
+                    location: self.location(start),
+                });
+                None
+            }
+        }
+    }
+
+    fn input_dir(&mut self, index: usize, dir: CfgInputDirectory, output_dir: &Path) -> InputDirectory {
+        let section = Section::InputDirectory {
+            index,
+            path: dir.path.get_ref().clone(),
+        };
+        let input_dir = InputDirectory {
+            priority: dir.priority,
+            path: self.path(&section, &dir.path),
+            filters: self.filter_set(&section, dir.filters, dir.accept_unmatched),
+        };
+        if condenser::check_output_dir(output_dir, [&input_dir]).is_err() {
+            self.errors.push(ConfigError::OverlappingDirectories {
+                section,
+                output_dir: output_dir.to_string_lossy().into_owned(),
+                location: self.location(dir.path.start()),
+            });
+        }
+        input_dir
+    }
+
+    fn orphan_policy(&mut self, cfg: CfgOrphanPolicy, output_dir: &Path, input_dirs: &[InputDirectory]) -> OrphanPolicy {
+        let trash_dir = cfg.trash_dir.map(|dir| {
+            let path = self.path(&Section::Orphans, &dir);
+            if let Err(err) = condenser::check_trash_dir(&path, output_dir, input_dirs) {
+                self.errors.push(ConfigError::OverlappingTrashDirectory {
+                    directory: err.directory.to_string_lossy().into_owned(),
+                    location: self.location(dir.start()),
+                });
+            }
+            path
+        });
+        OrphanPolicy {
+            protect: self.filter_set(&Section::Orphans, cfg.protect, false),
+            trash_dir,
+            max_deletions: cfg.max_deletions,
+            max_deletion_percent: cfg.max_deletion_percent,
+        }
+    }
+
+    fn transformer(&mut self, index: usize, cfg: CfgTransformerInstance) -> Option<TransformerInstance> {
+        use condenser::*;
+
+        let section = Section::Transformer {
+            index,
+            name: cfg.name.get_ref().clone(),
+        };
+        let filter = self.filter_set(&section, cfg.filters, cfg.accept_unmatched);
+        let transformer: Box<dyn Transformer> = match cfg.transformer {
+            CfgTranformerSelection::CopyTransformer => Box::new(CopyTransformer),
+            CfgTranformerSelection::CommandTransformer(cfg) => {
+                let cfg = *cfg;
+                let transform_command = self.command(&section, cfg.transform_command);
+                let check_command = cfg.check_command.map(|cmd| self.command(&section, cmd));
+                let transfomer = transformers::CommandTransformer {
+                    transform_command: transform_command?,
+                    check_command: match check_command {
+                        Some(check_command) => Some(check_command?),
+                        None => None,
+                    },
+                    output_file_extension: cfg.output_file_ext.map(|s| s.into())
+                };
+                Box::new(transfomer)
+            }
+        };
+
+        let mut inst = TransformerInstance::new(cfg.priority, cfg.overwrite.into(), cfg.name.into_inner(), transformer);
+        inst.max_jobs = cfg.max_jobs;
+        inst.filter = filter;
+        Some(inst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_every_error_with_its_location() {
+        let source = r#"
+output_dir = "/output"
+
+[[input_dirs]]
+priority = 100
+path = "/input"
+filters = [ { Glob = "*.flac" }, { Regex = "(" } ]
+
+[[transformers]]
+name = "Copy"
+priority = 50
+overwrite = "Always"
+filters = [ { Glob = "[" } ]
+transformer = "CopyTransformer"
+"#;
+        let config: Config = toml::from_str(source).unwrap();
+        let errors = match config.into_library(source) {
+            Ok(_) => panic!("Expected the config to be invalid"),
+            Err(errors) => errors,
+        };
+
+        let locations: Vec<_> = errors.iter().filter_map(|err| err.location()).collect();
+        assert_eq!(locations, [Location { line: 7, column: 34 }, Location { line: 13, column: 13 }]);
+        match &errors[1] {
+            ConfigError::InvalidFilter { section, filter, .. } => {
+                assert_eq!(section, &Section::Transformer { index: 0, name: "Copy".into() });
+                assert_eq!(filter, r#"{ Glob = "[" }"#);
+            }
+            err => panic!("Unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn named_definitions_are_substituted() {
+        let source = r#"
+output_dir = "/output"
+
+[filter_sets.audio]
+filters = [ { Glob = "*.flac" }, { Glob = "*.mp3" } ]
+
+[filter_sets.media]
+filters = [ { Named = "audio" }, { Glob = "*.jpg" } ]
+
+[commands.opus]
+program = "/usr/bin/ffmpeg"
+args = ["-i", "!INPUTPATH!", "-b:a", "!BITRATE!", "!OUTPUTPATH!"]
+params = { BITRATE = "128K" }
+
+[[input_dirs]]
+priority = 100
+path = "/input"
+filters = [ { Named = "media" } ]
+
+[[transformers]]
+name = "Compress"
+priority = 50
+overwrite = "Always"
+filters = [ { Named = "audio" } ]
+transformer = { CommandTransformer = { transform_command = { command = "opus", params = { BITRATE = "96K" } } } }
+"#;
+        let config: Config = toml::from_str(source).unwrap();
+        let library = match config.into_library(source) {
+            Ok(library) => library,
+            Err(errors) => panic!("Unexpected errors {:?}", errors),
+        };
+
+        let input_filters = &library.input_dirs[0].filters;
+        assert!(input_filters.is_acceptable("a.mp3"));
+        assert!(input_filters.is_acceptable("a.jpg"));
+        assert!(!input_filters.is_acceptable("a.txt"));
+        let transformer = &library.transformers[0];
+        assert!(transformer.filter.is_acceptable("a.flac"));
+        assert!(!transformer.filter.is_acceptable("a.jpg"));
+        let fingerprint = transformer.transformer.fingerprint();
+        assert!(fingerprint.contains(r#"Arg("96K")"#), "{}", fingerprint);
+    }
+
+    #[test]
+    fn commands_without_params_are_taken_literally() {
+        let command = |params: &[(&str, &str)]| {
+            transformers::FullCommand::try_from(CfgCommand {
+                command: None,
+                program: Some("/bin/echo".to_string()),
+                args: vec!["!INPUTPATH!".to_string(), "Hello!WORLD!".to_string(), "!BITRATE!".to_string()],
+                success_codes: None,
+                params: params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            })
+        };
+
+        let literal = format!("{:?}", command(&[]).unwrap());
+        assert!(literal.contains(r#"Arg("Hello!WORLD!")"#), "{}", literal);
+        assert!(literal.contains(r#"Arg("!BITRATE!")"#), "{}", literal);
+        assert!(command(&[("BITRATE", "96K")]).is_err());
+        let substituted = format!("{:?}", command(&[("BITRATE", "96K"), ("WORLD", " world")]).unwrap());
+        assert!(substituted.contains(r#"Arg("Hello world")"#), "{}", substituted);
+        assert!(substituted.contains(r#"Arg("96K")"#), "{}", substituted);
+    }
+
+    #[test]
+    fn filter_actions_and_unmatched_files() {
+        let source = r#"
+output_dir = "/output"
+
+[[input_dirs]]
+priority = 100
+path = "/input"
+filters = [ { Glob = "*.log", action = "Reject" }, { Glob = "*.txt", action = "Accept" }, { Regex = "^tmp" } ]
+accept_unmatched = true
+
+[[input_dirs]]
+priority = 50
+path = "/input2"
+filters = [ { Glob = "*.flac" } ]
+
+[[transformers]]
+name = "Copy"
+priority = 50
+overwrite = "Always"
+filters = [ { Glob = "*.log", action = "Reject" } ]
+transformer = "CopyTransformer"
+"#;
+        let config: Config = toml::from_str(source).unwrap();
+        let library = match config.into_library(source) {
+            Ok(library) => library,
+            Err(errors) => panic!("Unexpected errors {:?}", errors),
+        };
+
+        let filters = &library.input_dirs[0].filters;
+        assert!(matches!(filters.filters()[0].action(), FilterAction::Reject));
+        assert!(matches!(filters.filters()[2].action(), FilterAction::Accept));
+        assert!(!filters.is_acceptable("a.log"));
+        assert!(filters.is_acceptable("a.txt"));
+        assert!(filters.is_acceptable("tmp.log.bak"));
+        assert!(filters.is_acceptable("a.flac"));
+        let filters = &library.input_dirs[1].filters;
+        assert!(filters.is_acceptable("a.flac"));
+        assert!(!filters.is_acceptable("a.mp3"));
+        let filter = &library.transformers[0].filter;
+        assert!(!filter.is_acceptable("a.log"));
+        assert!(!filter.is_acceptable("a.flac"));
+
+        let invalid = source.replacen(r#"action = "Reject""#, r#"action = "Delete""#, 1);
+        assert!(toml::from_str::<Config>(&invalid).is_err());
+    }
+
+    #[test]
+    fn filters_reject_unknown_and_extra_keys() {
+        let source = r#"
+output_dir = "/output"
+
+[[input_dirs]]
+priority = 100
+path = "/input"
+filters = [ FILTER ]
+
+[[transformers]]
+name = "Copy"
+priority = 50
+overwrite = "Always"
+filters = [ { Set = { filters = [ { Glob = "*.log", action = "Reject" } ] } } ]
+transformer = "CopyTransformer"
+"#;
+        let parse = |filter: &str| toml::from_str::<Config>(&source.replace("FILTER", filter));
+
+        let config = parse(r#"{ Not = { Glob = "*.log" }, action = "Reject" }"#).unwrap();
+        let filter = config.input_dirs[0].filters[0].get_ref();
+        assert!(matches!(filter.pattern, CfgFilterPattern::Not(_)));
+        assert_eq!(filter.action, CfgFilterAction::Reject);
+
+        let err = parse(r#"{ Glob = "*.log", acton = "Reject" }"#).unwrap_err();
+        assert!(err.to_string().contains("unknown field `acton`"), "{}", err);
+        let err = parse(r#"{ Glob = "*.log", Regex = "flac" }"#).unwrap_err();
+        assert!(err.to_string().contains("a single pattern, found `Regex` as well"), "{}", err);
+        assert!(parse(r#"{ action = "Reject" }"#).is_err());
+        assert!(parse(r#"{ Set = { filters = [ { Glob = "*.log", Regex = "flac" } ] } }"#).is_err());
+        assert!(parse(r#"{ Glob = { pattern = "*.log", case_sensitive = false } }"#).is_ok());
+        assert!(parse(r#"{ Glob = { pattern = "*.log", case_sensitiv = false } }"#).is_err());
+        assert!(parse(r#"{ Regex = { pattern = "log", case_sensitive = false } }"#).is_ok());
+        assert!(parse(r#"{ Regex = { pattern = "log", case_sensitiv = false } }"#).is_err());
+    }
+
+    #[test]
+    fn overlapping_trash_dirs_are_rejected() {
+        let source = r#"
+output_dir = "/output"
+
+[[input_dirs]]
+priority = 100
+path = "/input"
+filters = [ { Glob = "*.flac" } ]
+
+[[transformers]]
+name = "Copy"
+priority = 50
+overwrite = "Always"
+filters = [ { Glob = "*.flac" } ]
+transformer = "CopyTransformer"
+
+[orphans]
+trash_dir = "TRASH"
+"#;
+        let convert = |trash_dir: &str| {
+            let source = source.replace("TRASH", trash_dir);
+            let config: Config = toml::from_str(&source).unwrap();
+            config.into_library(&source).map(|_| ()).map_err(|errors| errors[0].to_string())
+        };
+
+        assert_eq!(convert("/trash"), Ok(()));
+        assert_eq!(convert("/output/.condenser/trash"), Ok(()));
+        for (trash_dir, directory) in [
+            ("/output/trash", "/output"),
+            ("/", "/output"),
+            ("/output/.condenser/tmp", "/output/.condenser/tmp"),
+            ("/input/trash", "/input"),
+        ] {
+            let err = convert(trash_dir).unwrap_err();
+            assert!(err.ends_with(&format!("trash_dir overlaps '{}'", directory)), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// A line and column in the configuration file, both starting at 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// Finds the location of the byte offset within source.
+    pub fn from_offset(source: &str, offset: usize) -> Location {
+        let before = &source[..offset.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        Location {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// Identifies the section of the configuration file an error was found in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Section {
+    /// An entry of input_dirs, by index and path.
+    InputDirectory { index: usize, path: String },
+    /// An entry of transformers, by index and name.
+    Transformer { index: usize, name: String },
+    /// The output_dir setting.
+    OutputDirectory,
+    /// The orphans table.
+    Orphans,
+    /// An entry of filter_sets, by name.
+    FilterSet { name: String },
+    /// An entry of commands, by name.
+    Command { name: String },
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Section::InputDirectory { index, path } => write!(f, "input directory #{} '{}'", index + 1, path),
+            Section::Transformer { index, name } => write!(f, "transformer #{} '{}'", index + 1, name),
+            Section::OutputDirectory => write!(f, "output_dir"),
+            Section::Orphans => write!(f, "[orphans]"),
+            Section::FilterSet { name } => write!(f, "filter set '{}'", name),
+            Section::Command { name } => write!(f, "command '{}'", name),
+        }
+    }
+}
+
+/// An error in the configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file couldn't be read.
+    Read { path: PathBuf, source: std::io::Error },
+
+    /// A configuration file isn't valid TOML, or doesn't have the expected structure. path
+    /// is None if the configuration was merged from several layers, in which case the location
+    /// refers to the resolved configuration.
+    Parse {
+        path: Option<PathBuf>,
+        message: String,
+        location: Option<Location>,
+    },
+
+    /// The includes of a configuration file are invalid.
+    InvalidInclude { path: PathBuf, reason: String },
+
+    /// A --set override is invalid.
+    InvalidSetting { setting: String, reason: String },
+
+    /// A filter is invalid. filter is the filter as written in the configuration file.
+    InvalidFilter {
+        section: Section,
+        filter: String,
+        reason: String,
+        location: Location,
+    },
+
+    /// A command is invalid.
+    InvalidCommand {
+        section: Section,
+        reason: String,
+        location: Location,
+    },
+
+    /// A path contains an environment variable or home directory that can't be expanded.
+    InvalidPath {
+        section: Section,
+        path: String,
+        reason: String,
+        location: Location,
+    },
+
+    /// An input directory overlaps the output directory.
+    OverlappingDirectories {
+        section: Section,
+        output_dir: String,
+        location: Location,
+    },
+
+    /// The trash directory overlaps the output directory or an input directory.
+    OverlappingTrashDirectory { directory: String, location: Location },
+}
+
+impl ConfigError {
+    /// Creates a Parse error from a TOML error in the file at path, or in the resolved
+    /// configuration if path is None.
+    pub fn parse(path: Option<&Path>, err: toml::de::Error) -> ConfigError {
+        ConfigError::Parse {
+            path: path.map(|path| path.to_path_buf()),
+            message: err.to_string(),
+            location: err.line_col().map(|(line, column)| Location {
+                line: line + 1,
+                column: column + 1,
+            }),
+        }
+    }
+
+    /// Returns the location of the error within the configuration file, if known.
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            ConfigError::Read { .. } | ConfigError::InvalidInclude { .. } | ConfigError::InvalidSetting { .. } => None,
+            ConfigError::Parse { location, .. } => *location,
+            ConfigError::InvalidFilter { location, .. }
+            | ConfigError::InvalidCommand { location, .. }
+            | ConfigError::InvalidPath { location, .. }
+            | ConfigError::OverlappingDirectories { location, .. }
+            | ConfigError::OverlappingTrashDirectory { location, .. } => Some(*location),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, source } => {
+                write!(f, "Failed to read {}: {}", path.to_string_lossy(), source)
+            }
+            // TOML errors already include their location
+            ConfigError::Parse { path: Some(path), message, .. } => write!(f, "{}: {}", path.to_string_lossy(), message),
+            ConfigError::Parse { path: None, message, .. } => write!(f, "resolved configuration: {}", message),
+            ConfigError::InvalidInclude { path, reason } => {
+                write!(f, "{}: invalid include: {}", path.to_string_lossy(), reason)
+            }
+            ConfigError::InvalidSetting { setting, reason } => write!(f, "invalid --set '{}': {}", setting, reason),
+            ConfigError::InvalidFilter { section, filter, reason, location } => {
+                write!(f, "{}: {}: invalid filter {}: {}", location, section, filter, reason)
+            }
+            ConfigError::InvalidCommand { section, reason, location } => {
+                write!(f, "{}: {}: invalid command: {}", location, section, reason)
+            }
+            ConfigError::InvalidPath { section, path, reason, location } => {
+                write!(f, "{}: {}: invalid path '{}': {}", location, section, path, reason)
+            }
+            ConfigError::OverlappingDirectories { section, output_dir, location } => {
+                write!(f, "{}: {} overlaps the output directory '{}'", location, section, output_dir)
+            }
+            ConfigError::OverlappingTrashDirectory { directory, location } => {
+                write!(f, "{}: {}: trash_dir overlaps '{}'", location, Section::Orphans, directory)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Read { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
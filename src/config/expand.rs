@@ -0,0 +1,104 @@
+use std::path::PathBuf;
+
+/// Expands environment variables and the home directory in str:
+/// * `${VAR}` is replaced with the value of the environment variable VAR, which must be set
+/// * `${VAR:-default}` is replaced with the value of VAR, or default if VAR is unset or empty
+/// * a leading `~` followed by `/` or nothing is replaced with the home directory
+/// * `$${` is replaced with a literal `${`
+///
+/// Any other `$` is left as is, so that shell scripts given as arguments are unaffected.
+/// Defaults are expanded in turn.
+pub fn expand(str: &str) -> Result<String, String> {
+    expand_with(str, &|name| std::env::var(name).ok(), &std::env::home_dir)
+}
+
+/// Expands str as in expand, looking up variables with var and the home directory with home.
+fn expand_with(
+    str: &str,
+    var: &dyn Fn(&str) -> Option<String>,
+    home: &dyn Fn() -> Option<PathBuf>,
+) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = str;
+    if rest == "~" || rest.starts_with("~/") {
+        let home = home().ok_or_else(|| "The home directory is unknown".to_string())?;
+        result.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(after) = after.strip_prefix("${") {
+            result.push_str("${");
+            rest = after;
+            continue;
+        }
+        let expression = match after.strip_prefix('{') {
+            Some(expression) => expression,
+            None => {
+                result.push('$');
+                rest = after;
+                continue;
+            }
+        };
+        let end = find_closing_brace(expression).ok_or_else(|| format!("Unterminated '${{' in '{}'", str))?;
+        let (name, default) = match expression[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&expression[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid variable name '{}' in '{}'", name, str));
+        }
+        match (var(name), default) {
+            (Some(value), Some(_)) if !value.is_empty() => result.push_str(&value),
+            (Some(value), None) => result.push_str(&value),
+            (_, Some(default)) => result.push_str(&expand_with(default, var, home)?),
+            (None, None) => return Err(format!("Environment variable '{}' is not set", name)),
+        }
+        rest = &expression[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Finds the index of the brace closing an expression, allowing for nested expressions.
+fn find_closing_brace(expression: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (index, c) in expression.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(index),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_variables_and_home() {
+        let var = |name: &str| match name {
+            "MUSIC" => Some("/mnt/music".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let home = || Some(PathBuf::from("/home/user"));
+        let expand = |str| expand_with(str, &var, &home);
+
+        assert_eq!(expand("${MUSIC}/flac").unwrap(), "/mnt/music/flac");
+        assert_eq!(expand("~/Music").unwrap(), "/home/user/Music");
+        assert_eq!(expand("/data/~/x").unwrap(), "/data/~/x");
+        assert_eq!(expand("${EMPTY:-~/Music}").unwrap(), "/home/user/Music");
+        assert_eq!(expand("${MISSING:-${MUSIC}}/x").unwrap(), "/mnt/music/x");
+        assert_eq!(expand("${EMPTY}").unwrap(), "");
+        assert_eq!(expand("test \"$0\" $$ $${MUSIC}").unwrap(), "test \"$0\" $$ ${MUSIC}");
+        assert!(expand("${MISSING}").is_err());
+        assert!(expand("${MUSIC").is_err());
+        assert!(expand("${BAD NAME}").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+use super::{expand, ConfigError};
+
+/// The configuration once merged with its includes and overrides.
+pub struct ResolvedConfig {
+    /// The TOML text of the configuration.
+    pub text: String,
+
+    /// The path to the file text was read from, or None if text was produced by merging
+    /// several layers, in which case error locations refer to text.
+    pub path: Option<PathBuf>,
+}
+
+/// Reads the configuration file at path, and merges it with its layers, each overriding
+/// the last:
+/// 1. the files in its `include` array, in order, relative to the file including them
+/// 2. the file itself
+/// 3. the local override file next to it, e.g. `config.local.toml`, if it exists
+/// 4. each of the settings, in the form `key=value`
+///
+/// Tables are merged key by key, and any other value is replaced. An array can be merged
+/// with a table whose keys are indices into it, so that single elements can be overridden.
+/// Every error found is returned.
+pub fn resolve(path: &Path, settings: &[String]) -> Result<ResolvedConfig, Vec<ConfigError>> {
+    let mut layers = Layers {
+        errors: Vec::new(),
+        including: Vec::new(),
+        layered: false,
+    };
+    let (source, mut value) = layers.load(path).ok_or_else(|| std::mem::take(&mut layers.errors))?;
+
+    let local_path = local_path(path);
+    if local_path.is_file() {
+        layers.layered = true;
+        if let Some((_, local)) = layers.load(&local_path) {
+            if let Err(reason) = merge(&mut value, local, "") {
+                layers.invalid_include(&local_path, reason);
+            }
+        }
+    }
+    for setting in settings {
+        layers.layered = true;
+        match parse_setting(setting).and_then(|overlay| merge(&mut value, overlay, "")) {
+            Ok(()) => (),
+            Err(reason) => layers.errors.push(ConfigError::InvalidSetting {
+                setting: setting.clone(),
+                reason,
+            }),
+        }
+    }
+
+    if !layers.errors.is_empty() {
+        return Err(layers.errors);
+    }
+    if !layers.layered {
+        return Ok(ResolvedConfig {
+            text: source,
+            path: Some(path.to_path_buf()),
+        });
+    }
+    let mut text = String::new();
+    if let Value::Table(table) = &value {
+        write_table(&mut text, &[], table);
+    }
+    Ok(ResolvedConfig { text, path: None })
+}
+
+/// Returns the path to the local override file of the configuration file at path.
+fn local_path(path: &Path) -> PathBuf {
+    path.with_extension("local.toml")
+}
+
+/// Loads configuration files and their includes, collecting every error found.
+struct Layers {
+    /// The errors found so far.
+    errors: Vec<ConfigError>,
+
+    /// The canonical paths of the files whose includes are being loaded, outermost first.
+    including: Vec<PathBuf>,
+
+    /// Whether or not more than one layer has been found.
+    layered: bool,
+}
+
+impl Layers {
+    /// Reads and parses the file at path, returning its text and its value merged over
+    /// its includes, or None if it couldn't be read or parsed.
+    fn load(&mut self, path: &Path) -> Option<(String, Value)> {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(source) => {
+                self.errors.push(ConfigError::Read {
+                    path: path.to_path_buf(),
+                    source,
+                });
+                return None;
+            }
+        };
+        let mut value: Value = match toml::from_str(&source) {
+            Ok(value) => value,
+            Err(err) => {
+                self.errors.push(ConfigError::parse(Some(path), err));
+                return None;
+            }
+        };
+
+        let includes = match value.as_table_mut().and_then(|table| table.remove("include")) {
+            Some(includes) => includes,
+            None => return Some((source, value)),
+        };
+        let includes = match includes.try_into::<Vec<PathBuf>>() {
+            Ok(includes) => includes,
+            Err(_) => {
+                self.invalid_include(path, "include must be an array of paths".to_string());
+                return None;
+            }
+        };
+
+        self.layered = true;
+        let canonical_path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        self.including.push(canonical_path);
+        let mut merged = Value::Table(Default::default());
+        for include in includes {
+            let include = match expand(&include.to_string_lossy()) {
+                Ok(include) => dir.join(include),
+                Err(reason) => {
+                    self.invalid_include(path, reason);
+                    continue;
+                }
+            };
+            let canonical_include = std::fs::canonicalize(&include).unwrap_or_else(|_| include.clone());
+            if self.including.contains(&canonical_include) {
+                let reason = format!("including {} forms a loop", include.to_string_lossy());
+                self.invalid_include(path, reason);
+            } else if let Some((_, included)) = self.load(&include) {
+                if let Err(reason) = merge(&mut merged, included, "") {
+                    self.invalid_include(&include, reason);
+                }
+            }
+        }
+        self.including.pop();
+        if let Err(reason) = merge(&mut merged, value, "") {
+            self.invalid_include(path, reason);
+        }
+        Some((source, merged))
+    }
+
+    /// Records an error in the includes of the file at path.
+    fn invalid_include(&mut self, path: &Path, reason: String) {
+        self.errors.push(ConfigError::InvalidInclude {
+            path: path.to_path_buf(),
+            reason,
+        });
+    }
+}
+
+/// Merges overlay into base, where key_path is the dotted path to base. Tables are merged
+/// key by key, and arrays are merged with tables whose keys are all indices into them. Any
+/// other value in base is replaced. Fails if a table overlaying an array has a key that
+/// isn't an index into it.
+fn merge(base: &mut Value, overlay: Value, key_path: &str) -> Result<(), String> {
+    let join = |key: &str| match key_path {
+        "" => key.to_string(),
+        _ => format!("{}.{}", key_path, key),
+    };
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value, &join(&key))?,
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match key.parse::<usize>() {
+                    Ok(index) if index < base.len() => merge(&mut base[index], value, &join(&key))?,
+                    _ => {
+                        return Err(format!(
+                            "'{}' isn't an index into {}, which has {} element(s)",
+                            key,
+                            key_path,
+                            base.len()
+                        ))
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+    Ok(())
+}
+
+/// Writes table as TOML in the style of the sample configuration, so that the locations of
+/// errors in its values can be found: tables are written as sections, as are arrays of
+/// tables at the top level, and anything within an array is written inline.
+fn write_table(text: &mut String, path: &[&str], table: &toml::value::Table) {
+    use std::fmt::Write;
+
+    let top_level = path.is_empty();
+    for (key, value) in table.iter().filter(|(_, value)| !is_section(value, top_level)) {
+        let _ = writeln!(text, "{} = {}", write_key(key), write_inline(value));
+    }
+    for (key, value) in table.iter().filter(|(_, value)| is_section(value, top_level)) {
+        let mut path = path.to_vec();
+        path.push(key);
+        let header: Vec<String> = path.iter().map(|key| write_key(key)).collect();
+        match value {
+            Value::Table(table) => {
+                // Tables only containing sections don't need a header of their own.
+                if table.is_empty() || !table.values().all(|value| is_section(value, false)) {
+                    let _ = writeln!(text, "\n[{}]", header.join("."));
+                }
+                write_table(text, &path, table);
+            }
+            Value::Array(array) => {
+                for table in array.iter().filter_map(Value::as_table) {
+                    let _ = writeln!(text, "\n[[{}]]", header.join("."));
+                    for (key, value) in table {
+                        let _ = writeln!(text, "{} = {}", write_key(key), write_inline(value));
+                    }
+                }
+            }
+            _ => unreachable!("Expected a section to be a table or an array of tables"),
+        }
+    }
+}
+
+/// Returns whether or not value is written as a section, rather than inline.
+fn is_section(value: &Value, top_level: bool) -> bool {
+    match value {
+        Value::Table(_) => true,
+        Value::Array(array) => top_level && !array.is_empty() && array.iter().all(Value::is_table),
+        _ => false,
+    }
+}
+
+/// Writes value as an inline TOML value.
+fn write_inline(value: &Value) -> String {
+    match value {
+        Value::Array(array) => {
+            let values: Vec<String> = array.iter().map(write_inline).collect();
+            format!("[ {} ]", values.join(", "))
+        }
+        Value::Table(table) => {
+            let entries: Vec<String> =
+                table.iter().map(|(key, value)| format!("{} = {}", write_key(key), write_inline(value))).collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Writes key as a TOML key, quoting it unless it's a bare key.
+fn write_key(key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+/// Parses a setting in the form `key=value` into a table, where key may be dotted and value
+/// is a TOML value. Values that aren't valid TOML are taken as strings.
+fn parse_setting(setting: &str) -> Result<Value, String> {
+    let (key, value) = match setting.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+        _ => return Err("expected key=value".to_string()),
+    };
+    toml::from_str(&format!("{} = {}", key, value))
+        .or_else(|_| toml::from_str(&format!("{} = {}", key, Value::String(value.to_string()))))
+        .map_err(|err: toml::de::Error| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_override_tables_and_array_elements() {
+        let mut value: Value = toml::from_str(
+            r#"
+output_dir = "/output"
+[orphans]
+max_deletions = 10
+[[input_dirs]]
+priority = 100
+path = "/input"
+"#,
+        )
+        .unwrap();
+        for setting in ["output_dir=/mnt/out put", "orphans.trash_dir=\"/trash\"", "input_dirs.0.priority = 50"] {
+            merge(&mut value, parse_setting(setting).unwrap(), "").unwrap();
+        }
+
+        assert_eq!(value["output_dir"].as_str(), Some("/mnt/out put"));
+        assert_eq!(value["orphans"]["max_deletions"].as_integer(), Some(10));
+        assert_eq!(value["orphans"]["trash_dir"].as_str(), Some("/trash"));
+        assert_eq!(value["input_dirs"][0]["priority"].as_integer(), Some(50));
+        assert_eq!(value["input_dirs"][0]["path"].as_str(), Some("/input"));
+        assert!(parse_setting("output_dir").is_err());
+    }
+
+    #[test]
+    fn bad_array_indices_are_reported() {
+        let mut value: Value = toml::from_str(
+            r#"
+[[input_dirs]]
+priority = 100
+path = "/input"
+"#,
+        )
+        .unwrap();
+        let original = value.clone();
+
+        for (setting, key) in [("input_dirs.3.path=/x", "'3'"), ("input_dirs.first.path=/x", "'first'")] {
+            let err = merge(&mut value, parse_setting(setting).unwrap(), "").unwrap_err();
+            assert_eq!(err, format!("{} isn't an index into input_dirs, which has 1 element(s)", key));
+        }
+        assert_eq!(value, original);
+    }
+
+    #[test]
+    fn written_tables_parse_to_the_same_value() {
+        let value: Value = toml::from_str(
+            r#"
+output_dir = "/output"
+[filter_sets.audio]
+filters = [ { Glob = "*.flac" }, { Glob = { pattern = "*.MP3", case_sensitive = false } } ]
+[[input_dirs]]
+priority = 100
+path = "/input"
+filters = [ { Named = "audio" } ]
+[[transformers]]
+name = "Copy \"all\""
+transformer = { CommandTransformer = { transform_command = { command = "cp", params = { "ODD KEY" = "1" } } } }
+"#,
+        )
+        .unwrap();
+        let mut text = String::new();
+        write_table(&mut text, &[], value.as_table().unwrap());
+
+        assert!(text.contains(r#"filters = [ { Named = "audio" } ]"#), "{}", text);
+        assert_eq!(toml::from_str::<Value>(&text).unwrap(), value);
+    }
+}
@@ -1,30 +1,476 @@
 mod config;
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use condenser::run_transformations;
+use clap::{Parser, Subcommand};
+use config::Library;
+use condenser::{InputExplanation, InputOutcome, OrphanPolicy, OverwriteCheck, Plan, TransformerInstance};
+use log::{error, info, warn, LevelFilter};
 
-use condenser::TransformerInstance;
-use log::error;
+/// Merges and transforms the contents of one or more input directories into
+/// a single output directory.
+#[derive(Parser, Debug)]
+#[clap(name = "condenser", version, about)]
+struct Cli {
+    /// The path to the configuration file.
+    #[clap(short, long, global = true, default_value = "./config.toml")]
+    config: PathBuf,
 
-fn main() {
-    env_logger::Builder::new().filter_level(log::LevelFilter::max()).init();
+    /// Overrides a setting of the configuration, e.g. output_dir=/mnt/music. The key may be
+    /// dotted, and the value is read as TOML, or as a string if it isn't valid TOML. May be repeated.
+    #[clap(long = "set", global = true, value_name = "KEY=VALUE")]
+    settings: Vec<String>,
 
-    // TODO: This should be able to be specified from the command line.
-    let config = std::fs::read_to_string("./config.toml").unwrap();
-    let config: config::Config = toml::from_str(&config).unwrap();
-    
-    let mut input_dirs = Vec::new();
-    let output_dir_path: PathBuf = config.output_dir.into();
-    for dir in config.input_dirs {
-        match dir.try_into() {
-            Ok(dir) => input_dirs.push(dir),
-            Err(err) => error!("Failed to read config file: {}", err),
+    /// Increases logging verbosity. May be repeated.
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Decreases logging verbosity. May be repeated.
+    #[clap(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+
+    /// The number of files to transform concurrently - defaults to the number of CPUs.
+    #[clap(short, long, global = true)]
+    jobs: Option<usize>,
+
+    /// Marks the output directory as managed by condenser, allowing orphans to be deleted
+    /// from it even though it wasn't empty when first used.
+    #[clap(long, global = true)]
+    adopt: bool,
+
+    /// The command to run - defaults to run.
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+enum Command {
+    /// Claims inputs, deletes orphaned outputs and runs all transformers.
+    Run,
+    /// Claims inputs and reports the result without modifying the output directory.
+    Plan {
+        /// Prints the plan as JSON instead of a human readable report.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Loads and validates the configuration file, warning about likely mistakes.
+    Check {
+        /// Also scans the input directories, reporting how many files each transformer
+        /// claims and how many are left unclaimed.
+        #[clap(long)]
+        scan: bool,
+    },
+    /// Claims inputs and deletes orphaned outputs without running any transformers.
+    Clean,
+    /// Explains how an input file is filtered and claimed.
+    Explain {
+        /// The path to the input file.
+        path: PathBuf,
+    },
+    /// Prints the configuration once merged with its includes and overrides.
+    ShowConfig,
+}
+
+impl Cli {
+    /// Maps the verbosity flags onto a log level, defaulting to Info.
+    fn log_level(&self) -> LevelFilter {
+        const LEVELS: [LevelFilter; 6] = [
+            LevelFilter::Off,
+            LevelFilter::Error,
+            LevelFilter::Warn,
+            LevelFilter::Info,
+            LevelFilter::Debug,
+            LevelFilter::Trace,
+        ];
+        let index = 3 + i32::from(self.verbose) - i32::from(self.quiet);
+        LEVELS[index.clamp(0, LEVELS.len() as i32 - 1) as usize]
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    env_logger::Builder::new().filter_level(cli.log_level()).init();
+
+    let resolved = match config::resolve(&cli.config, &cli.settings) {
+        Ok(resolved) => resolved,
+        Err(errors) => {
+            log_config_errors(&cli.config, &errors);
+            return ExitCode::FAILURE;
+        }
+    };
+    let command = cli.command.clone().unwrap_or(Command::Run);
+    if command == Command::ShowConfig {
+        print!("{}", resolved.text);
+        return ExitCode::SUCCESS;
+    }
+
+    let mut library = match resolved.load() {
+        Ok(library) => library,
+        Err(errors) => {
+            log_config_errors(&cli.config, &errors);
+            if resolved.path.is_none() && errors.iter().any(|err| err.location().is_some()) {
+                info!("Locations refer to the merged configuration, which can be printed with show-config");
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    let Library {
+        transformers,
+        input_dirs,
+        output_dir: output_dir_path,
+        orphan_policy,
+    } = &mut library;
+
+    if cli.adopt && matches!(command, Command::Run | Command::Clean) {
+        if let Err(err) = condenser::write_marker(output_dir_path) {
+            error!("Failed to mark {} as managed: {}", output_dir_path.to_string_lossy(), err);
+            return ExitCode::FAILURE;
+        }
+        info!("Marked {} as managed by condenser", output_dir_path.to_string_lossy());
+    }
+
+    match command {
+        Command::Run => {
+            let jobs = cli.jobs.unwrap_or_else(|| {
+                std::thread::available_parallelism().map_or(1, |jobs| jobs.get())
+            });
+            match condenser::run_transformations(transformers, input_dirs.iter(), output_dir_path, orphan_policy, jobs) {
+                Ok(0) => (),
+                Ok(_) => return ExitCode::FAILURE,
+                Err(err) => {
+                    error!("Failed to run transformations: {}", err);
+                    suggest_adopt(&err);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        Command::Plan { json } => {
+            let result = condenser::plan_transformations(transformers, input_dirs.iter(), output_dir_path)
+                .map_err(|err| err.into())
+                .and_then(|plan| match json {
+                    true => print_plan_json(&plan),
+                    false => print_plan(&plan, transformers, orphan_policy),
+                });
+            if let Err(err) = result {
+                error!("Failed to plan transformations: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+        Command::Check { scan } => {
+            let lints = condenser::lint(transformers, input_dirs.iter());
+            for lint in &lints {
+                warn!("{}", lint);
+            }
+            info!(
+                "Configuration is valid: {} input directories, {} transformers, output to {}, {} warning(s)",
+                input_dirs.len(),
+                transformers.len(),
+                output_dir_path.to_string_lossy(),
+                lints.len()
+            );
+            if scan {
+                match condenser::plan_transformations(transformers, input_dirs.iter(), output_dir_path) {
+                    Ok(plan) => print_scan(&plan),
+                    Err(err) => {
+                        error!("Failed to scan input directories: {}", err);
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+        }
+        Command::Clean => {
+            let result = condenser::plan_transformations(transformers, input_dirs.iter(), output_dir_path)
+                .and_then(|plan| {
+                    let count = condenser::delete_orphans(&plan, orphan_policy)?;
+                    info!("Removed {} orphaned file(s)", count);
+                    let count = condenser::prune_empty_dirs(&plan.output_dir, orphan_policy)?;
+                    info!("Removed {} empty director(ies)", count);
+                    condenser::clean_temp_files(&plan.output_dir)
+                });
+            if let Err(err) = result {
+                error!("Failed to delete orphaned files: {}", err);
+                suggest_adopt(&err);
+                return ExitCode::FAILURE;
+            }
+        }
+        Command::ShowConfig => unreachable!("The configuration is printed before it's loaded"),
+        Command::Explain { path } => {
+            let result = condenser::explain_input(transformers, input_dirs.iter(), output_dir_path, &path);
+            match result {
+                Ok(explanations) if explanations.is_empty() => {
+                    error!("{} is not within any input directory", path.to_string_lossy());
+                    return ExitCode::FAILURE;
+                }
+                Ok(explanations) => print_explanations(&explanations),
+                Err(err) => {
+                    error!("Failed to explain {}: {}", path.to_string_lossy(), err);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Suggests --adopt if err was caused by the output directory not being managed by condenser.
+fn suggest_adopt(err: &std::io::Error) {
+    if err.get_ref().is_some_and(|err| err.is::<condenser::UnmanagedDirectoryError>()) {
+        info!("If the output directory only contains condenser's outputs, re-run with --adopt");
+    }
+}
+
+/// Prints every claimed transformation and whether or not it will be skipped, followed
+/// by every orphan that would be deleted. Nothing is written to the output directory.
+/// Claims that can't be checked are printed as errors, and fail the plan once every
+/// claim has been printed.
+fn print_plan(
+    plan: &Plan,
+    transformers: &[TransformerInstance],
+    orphan_policy: &OrphanPolicy,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let state = condenser::StateDatabase::load(&plan.output_dir).unwrap_or_else(|err| {
+        warn!("Failed to load the state database, all outputs will be considered unknown - {}", err);
+        condenser::StateDatabase::empty(&plan.output_dir)
+    });
+    let mut transform_count = 0;
+    let mut skip_count = 0;
+    let mut error_count = 0;
+    for transformer_plan in &plan.transformers {
+        let transformer = &transformers[transformer_plan.transformer];
+        println!("Transformer '{}':", transformer.name);
+        for claim in &transformer_plan.claims {
+            let input_path = claim.input_path();
+            let output_path = plan.output_path(claim);
+            let record = state.get(&claim.output_path);
+            match transformer.check_overwrite(&input_path, &output_path, record) {
+                Ok(OverwriteCheck::Transform(reason)) => {
+                    transform_count += 1;
+                    println!(
+                        "  transform {} -> {} ({})",
+                        input_path.to_string_lossy(),
+                        output_path.to_string_lossy(),
+                        reason
+                    );
+                }
+                Ok(OverwriteCheck::Skip(_)) => {
+                    skip_count += 1;
+                    println!(
+                        "  skip      {} -> {} (overwrite: {:?})",
+                        input_path.to_string_lossy(),
+                        output_path.to_string_lossy(),
+                        transformer.overwrite_behavior
+                    );
+                }
+                Err(err) => {
+                    error_count += 1;
+                    println!(
+                        "  error     {} -> {} ({})",
+                        input_path.to_string_lossy(),
+                        output_path.to_string_lossy(),
+                        err
+                    );
+                }
+            }
+        }
+    }
+
+    let mut delete_count = 0;
+    let action = match orphan_policy.trash_dir {
+        Some(_) => "trash    ",
+        None => "delete   ",
+    };
+    println!("Orphans:");
+    for orphan in &plan.orphans {
+        if orphan_policy.is_protected(&plan.output_dir, orphan) {
+            println!("  keep      {} (protected)", plan.output_dir.join(orphan).to_string_lossy());
+        } else {
+            delete_count += 1;
+            println!("  {} {}", action, plan.output_dir.join(orphan).to_string_lossy());
+        }
+    }
+    if let Err(err) = orphan_policy.check_limits(delete_count, plan.output_file_count) {
+        println!("  {} - no orphans will be deleted", err);
+    } else if delete_count > 0 {
+        if let Err(err) = condenser::check_ownership(&plan.output_dir) {
+            println!("  {} - no orphans will be deleted without --adopt", err);
+        }
+    }
+
+    println!("Conflicts:");
+    for conflict in &plan.conflicts {
+        println!(
+            "  {} ('{}') lost '{}' to {} ('{}')",
+            conflict.loser.input_dir.join(&conflict.loser.file_path).to_string_lossy(),
+            conflict.loser.transformer,
+            conflict.output_id.0.to_string_lossy(),
+            conflict.winner.input_dir.join(&conflict.winner.file_path).to_string_lossy(),
+            conflict.winner.transformer
+        );
+    }
+
+    println!(
+        "{} file(s) to transform, {} file(s) skipped, {} orphan(s) to delete, {} input(s) unclaimed, {} conflict(s)",
+        transform_count,
+        skip_count,
+        delete_count,
+        plan.unclaimed.len(),
+        plan.conflicts.len()
+    );
+    if error_count > 0 {
+        return Err(format!("{} file(s) couldn't be checked", error_count).into());
+    }
+    Ok(())
+}
+
+/// Prints how many files each transformer claims, and how many files in each input
+/// directory are unclaimed, by extension.
+fn print_scan(plan: &Plan) {
+    println!("Claims:");
+    for transformer_plan in &plan.transformers {
+        println!("  '{}': {} file(s)", transformer_plan.name, transformer_plan.claims.len());
+        if transformer_plan.claims.is_empty() {
+            warn!("Transformer '{}' didn't claim any files", transformer_plan.name);
+        }
+    }
+
+    let mut unclaimed: BTreeMap<&Path, BTreeMap<String, usize>> = BTreeMap::new();
+    for input in &plan.unclaimed {
+        let extension = match input.file_path.extension() {
+            Some(extension) => format!(".{}", extension.to_string_lossy()),
+            None => "no extension".to_string(),
+        };
+        *unclaimed.entry(&input.input_dir).or_default().entry(extension).or_default() += 1;
+    }
+    println!("Unclaimed:");
+    for (input_dir, extensions) in unclaimed {
+        let counts: Vec<String> = extensions.iter().map(|(extension, count)| format!("{}: {}", extension, count)).collect();
+        println!(
+            "  {}: {} file(s) ({})",
+            input_dir.to_string_lossy(),
+            extensions.values().sum::<usize>(),
+            counts.join(", ")
+        );
+    }
+
+    println!(
+        "{} file(s) claimed, {} input(s) unclaimed, {} conflict(s)",
+        plan.claims().count(),
+        plan.unclaimed.len(),
+        plan.conflicts.len()
+    );
+}
+
+/// Prints how an input file is handled by each input directory containing it.
+fn print_explanations(explanations: &[InputExplanation]) {
+    for explanation in explanations {
+        println!(
+            "Input directory {} (priority {}), as {}:",
+            explanation.input_dir.to_string_lossy(),
+            explanation.priority,
+            explanation.file_path.to_string_lossy()
+        );
+        println!("  input filters: {}", explanation.filter);
+        for transformer in &explanation.transformers {
+            println!("  transformer '{}' (priority {}):", transformer.name, transformer.priority);
+            println!("    filters: {}", transformer.filter);
+            match (transformer.can_handle, &transformer.output_id) {
+                (Some(true), Some(output_id)) => {
+                    println!("    can handle: yes, output '{}'", output_id.0.to_string_lossy())
+                }
+                (Some(_), _) => println!("    can handle: no"),
+                (None, _) => (),
+            }
+        }
+        match &explanation.outcome {
+            InputOutcome::Claimed { transformer, output_path } => println!(
+                "  claimed by '{}' -> {}",
+                transformer,
+                output_path.to_string_lossy()
+            ),
+            InputOutcome::Blocked { output_id, winner } => println!(
+                "  blocked: output '{}' was already claimed by {} ('{}')",
+                output_id.0.to_string_lossy(),
+                winner.input_dir.join(&winner.file_path).to_string_lossy(),
+                winner.transformer
+            ),
+            InputOutcome::Unclaimed => println!("  unclaimed: no transformer accepted and could handle it"),
+            InputOutcome::NotEnumerated => println!("  not enumerated: rejected by the input filters, or not a file"),
         }
     }
+}
+
+/// Prints the plan to stdout as JSON.
+fn print_plan_json(plan: &Plan) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    println!("{}", serde_json::to_string_pretty(plan)?);
+    Ok(())
+}
+
+/// Logs every error found while loading the configuration file at path.
+fn log_config_errors(path: &Path, errors: &[config::ConfigError]) {
+    for err in errors {
+        error!("{}", err);
+    }
+    error!("Failed to load config file {}: {} error(s)", path.to_string_lossy(), errors.len());
+}
 
-    let mut ts: Vec<TransformerInstance> = config.transformers
-        .into_iter().map(|t| t.try_into().expect("Invalid transformer instance config."))
-        .collect();
+#[cfg(test)]
+mod tests {
+    use condenser::{CopyTransformer, FilterSet, InputDirectory, OverwriteBehavior};
 
-    run_transformations(&mut ts, &input_dirs, output_dir_path);
+    use super::*;
+
+    /// Returns the path and contents of every file within dir, recursively.
+    fn snapshot(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut files = BTreeMap::new();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(snapshot(&path));
+            } else {
+                files.insert(path.clone(), std::fs::read(&path).unwrap());
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn plan_leaves_the_output_directory_untouched() {
+        let test_dir = std::env::temp_dir().join("condenser-print-plan-test");
+        let _ = std::fs::remove_dir_all(&test_dir);
+        let input_dir = test_dir.join("input");
+        let output_dir = test_dir.join("output");
+        std::fs::create_dir_all(&input_dir).unwrap();
+        std::fs::create_dir_all(&output_dir).unwrap();
+        for name in ["a.flac", "b.flac", "c.flac"] {
+            std::fs::write(input_dir.join(name), name).unwrap();
+        }
+        std::fs::write(output_dir.join("a.flac"), "existing").unwrap();
+        std::fs::write(output_dir.join("b.flac"), "existing").unwrap();
+        std::fs::write(output_dir.join("orphan.txt"), "orphan").unwrap();
+        let before = snapshot(&output_dir);
+
+        let mut filters = FilterSet::new();
+        filters.accept_unmatched = true;
+        let input_dirs = [InputDirectory {
+            priority: 100,
+            filters,
+            path: input_dir.clone(),
+        }];
+        let mut transformer =
+            TransformerInstance::new(50, OverwriteBehavior::IfNewer, "Copy".to_string(), Box::new(CopyTransformer));
+        transformer.filter.accept_unmatched = true;
+        let mut transformers = [transformer];
+        let plan = condenser::plan_transformations(&mut transformers, input_dirs.iter(), &output_dir).unwrap();
+        // An input that disappears after planning can't be checked, but doesn't stop the
+        // rest of the plan from being printed.
+        std::fs::remove_file(input_dir.join("b.flac")).unwrap();
+        let result = print_plan(&plan, &transformers, &OrphanPolicy::default());
+
+        let after = snapshot(&output_dir);
+        std::fs::remove_dir_all(&test_dir).unwrap();
+        assert_eq!(result.unwrap_err().to_string(), "1 file(s) couldn't be checked");
+        assert_eq!(before, after);
+    }
 }
@@ -2,11 +2,11 @@ use std::{
     ffi::OsString,
     fmt::Display,
     path::{Path, PathBuf},
-    process::ExitStatus,
+    process::{ExitStatus, Output},
 };
 
 use condenser::{InputId, OutputId, Transformer};
-use log::{trace, warn};
+use log::{debug, trace, warn};
 
 /// An argument to pass to the command transformer.
 #[derive(Debug)]
@@ -23,21 +23,22 @@ pub enum CommandArgument {
 
 #[derive(Debug)]
 pub enum CommandError {
-    /// A command has exited with a non-zero status code.
-    ExitStatus(ExitStatus),
+    /// A command has exited with a status that doesn't indicate success, or was
+    /// terminated by a signal. Contains the captured stderr.
+    ExitStatus(ExitStatus, String),
 
     /// An error occurred executing the command.
-    Error(Box<dyn std::error::Error>),
+    Error(Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl CommandError {
-    /// Creates a new CommandError from an exit status.
-    pub fn from_status_code(exit_status: ExitStatus) -> CommandError {
-        CommandError::ExitStatus(exit_status)
+    /// Creates a new CommandError from an exit status and the captured stderr.
+    pub fn from_status_code(exit_status: ExitStatus, stderr: String) -> CommandError {
+        CommandError::ExitStatus(exit_status, stderr)
     }
 
     /// Creates a new Command error from an Error.
-    pub fn from_error(error: Box<dyn std::error::Error>) -> CommandError {
+    pub fn from_error(error: Box<dyn std::error::Error + Send + Sync>) -> CommandError {
         CommandError::Error(error)
     }
 }
@@ -45,7 +46,8 @@ impl CommandError {
 impl Display for CommandError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CommandError::ExitStatus(status) => write!(f, "Exit status: {}", status),
+            CommandError::ExitStatus(status, stderr) if stderr.trim().is_empty() => write!(f, "Exit status: {}", status),
+            CommandError::ExitStatus(status, stderr) => write!(f, "Exit status: {} - stderr:\n{}", status, stderr.trim_end()),
             CommandError::Error(err) => write!(f, "Error: {}", err),
         }
     }
@@ -54,7 +56,7 @@ impl Display for CommandError {
 impl std::error::Error for CommandError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            CommandError::ExitStatus(_) => None,
+            CommandError::ExitStatus(..) => None,
             CommandError::Error(err) => Some(err.as_ref()),
         }
     }
@@ -76,20 +78,56 @@ pub struct FullCommand {
 
     /// An ordered list of arguments to pass to the executable.
     pub args: Vec<CommandArgument>,
+
+    /// The exit codes that indicate the command succeeded. A command terminated
+    /// by a signal never succeeds.
+    pub success_codes: Vec<i32>,
 }
 
 impl FullCommand {
-    /// Creates a new instance of FullCommand with the provided program and no arguments.
+    /// Creates a new instance of FullCommand with the provided program and no arguments,
+    /// that succeeds only when exiting with a code of zero.
     pub fn new(program: OsString) -> FullCommand {
         FullCommand {
             program,
             args: Vec::new(),
+            success_codes: vec![0],
         }
     }
 
+    /// Tests whether or not the exit status indicates the command succeeded.
+    pub fn is_success(&self, status: &ExitStatus) -> bool {
+        status.code().map_or(false, |code| self.success_codes.contains(&code))
+    }
+
     /// Executes the program with the specifed arguments, substituting input and output
-    /// for any arguments equal to InputPath or OutputPath.
-    pub fn execute(&self, input: &Path, output: &Path) -> Result<i32, Box<dyn std::error::Error>> {
+    /// for any arguments equal to InputPath or OutputPath. Fails if the exit status
+    /// doesn't indicate success.
+    pub fn execute(&self, input: &Path, output: &Path) -> Result<(), CommandError> {
+        let output = self.run(input, output)?;
+        if self.is_success(&output.status) {
+            Ok(())
+        } else {
+            Err(CommandError::from_status_code(
+                output.status,
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ))
+        }
+    }
+
+    /// Tests whether or not any argument is substituted with the input path.
+    pub fn takes_input_path(&self) -> bool {
+        self.args.iter().any(|arg| matches!(arg, CommandArgument::InputPath))
+    }
+
+    /// Tests whether or not any argument is substituted with the output path.
+    pub fn takes_output_path(&self) -> bool {
+        self.args.iter().any(|arg| matches!(arg, CommandArgument::OutputPath))
+    }
+
+    /// Runs the program to completion, capturing its output, without interpreting
+    /// the exit status.
+    pub fn run(&self, input: &Path, output: &Path) -> Result<Output, CommandError> {
         use std::process::Command;
 
         let args = self.args.iter().map(|arg| match arg {
@@ -105,27 +143,28 @@ impl FullCommand {
         );
 
         // TODO: Support environment variables
-        let output = Command::new(&self.program).args(args).output()?;
+        let output = Command::new(&self.program)
+            .args(args)
+            .output()
+            .map_err(|err| CommandError::from_error(Box::new(err)))?;
 
-        if output.status.success() {
+        if self.is_success(&output.status) {
             trace!("Command {} succeeded - stderr:\n{}\nstdout:\n{}",
                 &self.program.to_string_lossy(),
                 String::from_utf8_lossy(&output.stderr),
                 String::from_utf8_lossy(&output.stdout)
             )
         } else {
-            warn!("Command {} failed - stderr:\n{}\nstdout:\n{}",
+            debug!("Command {} failed with {} - stderr:\n{}\nstdout:\n{}",
                 &self.program.to_string_lossy(),
+                output.status,
                 String::from_utf8_lossy(&output.stderr),
                 String::from_utf8_lossy(&output.stdout)
             )
         }
 
         // TODO: Optionally error if STDERR is non-empty?
-        // TODO: Check return status and maybe output?
-        // TODO: More robust status handling.
-        let code = output.status.code().unwrap_or_default();
-        Ok(code)
+        Ok(output)
     }
 }
 
@@ -136,8 +175,8 @@ pub struct CommandTransformer {
     pub transform_command: FullCommand,
 
     /// The command to check the ability of the transformer to handle the input
-    /// file. If None, it is assumed that the transformer can always handle
-    /// the input file.
+    /// file - a successful exit status means the file can be handled. If None, it
+    /// is assumed that the transformer can always handle the input file.
     pub check_command: Option<FullCommand>,
 
     /// The output file extension - uses the input file name if None.
@@ -145,8 +184,37 @@ pub struct CommandTransformer {
 }
 
 impl Transformer for CommandTransformer {
-    fn can_handle(&self, _input: &InputId) -> bool {
-        true
+    fn can_handle(&self, input: &InputId) -> bool {
+        let check_command = match &self.check_command {
+            Some(check_command) => check_command,
+            None => return true,
+        };
+
+        // The check command doesn't produce an output, so OutputPath is substituted
+        // with the relative path the output would have.
+        let input_path = input.dir_path().join(input.file_path());
+        let output_path = self.determine_output_path(input);
+        match check_command.run(&input_path, &output_path) {
+            Ok(output) => {
+                let can_handle = check_command.is_success(&output.status);
+                if !can_handle {
+                    debug!(
+                        "Check command declined '{}' with {}",
+                        input_path.to_string_lossy(),
+                        output.status
+                    );
+                }
+                can_handle
+            }
+            Err(err) => {
+                warn!(
+                    "Check command failed to run for '{}', skipping it - {}",
+                    input_path.to_string_lossy(),
+                    err
+                );
+                false
+            }
+        }
     }
 
     fn determine_output_id(&self, input: &InputId) -> OutputId {
@@ -160,7 +228,7 @@ impl Transformer for CommandTransformer {
         }
     }
 
-    fn transform(&self, input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    fn transform(&self, input: &Path, output: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         // Create the parent directory if it doesn't exist
         if let Some(output_parent) = output.parent() {
             std::fs::create_dir_all(output_parent).map_err(Box::new)?;
@@ -169,4 +237,127 @@ impl Transformer for CommandTransformer {
         self.transform_command.execute(input, output)?;
         Ok(())
     }
+
+    fn handles_all(&self) -> bool {
+        self.check_command.is_none()
+    }
+
+    fn lint(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.transform_command.takes_input_path() {
+            warnings.push("the transform command has no !INPUTPATH! argument, so it never receives the input file".to_string());
+        }
+        if !self.transform_command.takes_output_path() {
+            warnings.push("the transform command has no !OUTPUTPATH! argument, so it never receives the output file".to_string());
+        }
+        if let Some(check_command) = &self.check_command {
+            if !check_command.takes_input_path() {
+                warnings.push("the check command has no !INPUTPATH! argument, so it can't inspect the input file".to_string());
+            }
+        }
+        if let Some(ext) = &self.output_file_extension {
+            if ext.to_string_lossy().starts_with('.') {
+                warnings.push(format!(
+                    "the output file extension '{}' starts with a '.', which will be doubled",
+                    ext.to_string_lossy()
+                ));
+            }
+        }
+        warnings
+    }
+
+    fn fingerprint(&self) -> String {
+        // The check command and success codes don't affect the output, so they're
+        // deliberately left out.
+        format!(
+            "CommandTransformer {{ program: {:?}, args: {:?}, output_file_extension: {:?} }}",
+            self.transform_command.program,
+            self.transform_command.args,
+            self.output_file_extension
+        )
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn shell(script: &str) -> FullCommand {
+        let mut cmd = FullCommand::new("/bin/sh".into());
+        cmd.args.push(CommandArgument::Arg("-c".into()));
+        cmd.args.push(CommandArgument::Arg(script.into()));
+        cmd
+    }
+
+    #[test]
+    fn zero_exit_code_succeeds() {
+        let cmd = shell("exit 0");
+        assert!(cmd.execute(Path::new("/in"), Path::new("/out")).is_ok());
+    }
+
+    #[test]
+    fn non_zero_exit_code_fails_with_stderr() {
+        let cmd = shell("echo broken >&2; exit 3");
+        match cmd.execute(Path::new("/in"), Path::new("/out")) {
+            Err(CommandError::ExitStatus(status, stderr)) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr.trim(), "broken");
+            }
+            result => panic!("Expected exit status error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn custom_success_codes() {
+        let mut cmd = shell("exit 1");
+        cmd.success_codes = vec![0, 1];
+        assert!(cmd.execute(Path::new("/in"), Path::new("/out")).is_ok());
+
+        let cmd = FullCommand { success_codes: vec![1], ..shell("exit 0") };
+        assert!(cmd.execute(Path::new("/in"), Path::new("/out")).is_err());
+    }
+
+    #[test]
+    fn check_command_exit_status_determines_can_handle() {
+        // The script is run with the input path as $0.
+        let mut check_command = shell("test \"$0\" = /music/a.flac");
+        check_command.args.push(CommandArgument::InputPath);
+        let transformer = CommandTransformer {
+            transform_command: shell("exit 0"),
+            check_command: Some(check_command),
+            output_file_extension: None,
+        };
+
+        let dir = Path::new("/music");
+        assert!(transformer.can_handle(&InputId::new(dir, Path::new("a.flac"))));
+        assert!(!transformer.can_handle(&InputId::new(dir, Path::new("b.flac"))));
+    }
+
+    #[test]
+    fn lint_reports_missing_paths() {
+        let mut transform_command = shell("cp \"$0\" \"$1\"");
+        transform_command.args.push(CommandArgument::InputPath);
+        let mut transformer = CommandTransformer {
+            transform_command,
+            check_command: Some(shell("exit 0")),
+            output_file_extension: Some(".ogg".into()),
+        };
+        assert_eq!(transformer.lint().len(), 3);
+        assert!(!transformer.handles_all());
+
+        transformer.transform_command.args.push(CommandArgument::OutputPath);
+        transformer.check_command = None;
+        transformer.output_file_extension = Some("ogg".into());
+        assert!(transformer.lint().is_empty());
+        assert!(transformer.handles_all());
+    }
+
+    #[test]
+    fn signal_fails() {
+        let cmd = shell("kill -9 $$");
+        assert!(matches!(
+            cmd.execute(Path::new("/in"), Path::new("/out")),
+            Err(CommandError::ExitStatus(..))
+        ));
+    }
 }